@@ -0,0 +1,212 @@
+//! 从 tenhou.net 下载牌谱，并缓存在用户的缓存目录中。
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use mjai_reviewer::parse_tenhou_json;
+
+use crate::{log, warning};
+
+/// 下载天凤牌谱的设置
+#[derive(Default)]
+pub struct DownloadOptions {
+    pub max_retries: u32,
+    /// 第一次重试前的等待时间，之后每次加倍
+    pub retry_delay: Duration,
+    /// `--user-agent` 和 `--header` 指定的请求头，可能含有 cookie 等凭据，不会出现在日志中
+    pub user_agent: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+/// 从天凤牌谱 URL（如 `https://tenhou.net/0/?log=2019050417gm-0029-0000-4f2a8622&tw=2`）
+/// 或单独的牌谱 ID 中提取牌谱 ID，不是牌谱 URL/ID 时返回 `None`。
+pub fn tenhou_log_id(input: &str) -> Option<&str> {
+    let log_id = match input.split_once("log=") {
+        Some((_, query)) => query.split('&').next().unwrap_or_default(),
+        None => input,
+    };
+
+    // 牌谱 ID 形如 2019050417gm-0029-0000-4f2a8622
+    let mut parts = log_id.split('-');
+    let date = parts.next()?.strip_suffix("gm")?;
+    let is_hex = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let valid = date.len() == 10
+        && date.bytes().all(|b| b.is_ascii_digit())
+        && parts.by_ref().take(3).filter(|s| is_hex(s)).count() == 3
+        && parts.next().is_none();
+    valid.then_some(log_id)
+}
+
+/// 解析 `--header` 的 `名称: 值`
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| "expected `Name: value`".to_owned())?;
+    let name = name.trim();
+    let valid_name = !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if !valid_name {
+        return Err(format!("invalid header name {name:?}"));
+    }
+    Ok((name.to_owned(), value.trim().to_owned()))
+}
+
+/// 下载天凤牌谱并缓存到 [`cache_dir`]，返回缓存文件路径。
+///
+/// 缓存按牌谱 ID 命名，已缓存的牌谱不会重复下载。只缓存能解析为 tenhou.net/6 牌谱的响应，
+/// HTTP 200 返回的错误页面等不会被当作牌谱保存。
+pub fn tenhou_log(log_id: &str, opts: &DownloadOptions) -> Result<PathBuf> {
+    let cache_dir = cache_dir()?;
+    let cache_path = cache_dir.join(format!("{log_id}.json"));
+    if cache_path.is_file() {
+        log!("using cached tenhou log {log_id}: {cache_path:?}");
+        return Ok(cache_path);
+    }
+
+    log!("downloading tenhou log {log_id}...");
+    let body = fetch(log_id, opts)?;
+    parse_tenhou_json(&body)
+        .with_context(|| format!("tenhou.net did not return a valid log for {log_id}"))?;
+
+    fs::create_dir_all(&cache_dir)
+        .with_context(|| format!("failed to create cache directory: {cache_dir:?}"))?;
+    // 先写到以 create_new 新建的临时文件再重命名：不会跟随预先放置的符号链接，
+    // 其他进程也不会读到写了一半的缓存
+    let tmp_path = cache_path
+        .with_extension(format!("{}.{:?}.tmp", process::id(), thread::current().id()));
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(body.as_bytes()))
+        .and_then(|()| fs::rename(&tmp_path, &cache_path))
+        .inspect_err(|_| drop(fs::remove_file(&tmp_path)))
+        .with_context(|| format!("failed to write cache file: {cache_path:?}"))?;
+    Ok(cache_path)
+}
+
+/// 从 tenhou.net 下载牌谱 JSON。
+///
+/// 网络错误、HTTP 429 和 5xx 会按 `opts` 的设置以指数退避重试，HTTP 429 带有 `Retry-After`
+/// 时按其等待。请求头见 [`request`]。
+fn fetch(log_id: &str, opts: &DownloadOptions) -> Result<String> {
+    let url = format!("https://tenhou.net/5/mjlog2json.cgi?{log_id}");
+    let referer = format!("https://tenhou.net/6/?log={log_id}");
+    let mut attempt = 0;
+    let result = loop {
+        let result = request(&url, &referer, opts).call();
+
+        let Some(retry_after) = retry_after(&result).filter(|_| attempt < opts.max_retries) else {
+            break result;
+        };
+        let reason = result.err().map(|e| e.to_string()).unwrap_or_default();
+
+        let backoff = opts.retry_delay.saturating_mul(1 << attempt.min(16));
+        let delay = retry_after.unwrap_or(backoff);
+        attempt += 1;
+        warning!("failed to download tenhou log {log_id} ({reason}), \
+                  retrying in {}s ({attempt}/{})",
+                 delay.as_secs_f32(), opts.max_retries);
+        thread::sleep(delay);
+    };
+
+    match result {
+        Ok(res) => res
+            .into_string()
+            .with_context(|| format!("failed to read tenhou log {log_id} from response")),
+        Err(ureq::Error::Status(404, _)) => {
+            bail!("tenhou log {log_id} not found (HTTP 404), check that the log ID is correct")
+        }
+        Err(ureq::Error::Status(429, _)) => {
+            bail!("rate limited by tenhou.net (HTTP 429) while downloading {log_id}, \
+                   wait a while and retry")
+        }
+        Err(ureq::Error::Status(code, _)) => {
+            bail!("tenhou.net returned HTTP {code} while downloading {log_id}")
+        }
+        Err(e) => Err(e).with_context(|| format!("failed to download tenhou log {log_id}")),
+    }
+}
+
+/// 创建下载请求。`opts` 中的请求头在 `Referer` 之后设置，同名时覆盖它。
+///
+/// 请求头的值可能含有 cookie 等凭据，不要输出到日志中；ureq 的错误信息只含有 URL 和状态，
+/// 不含请求头。
+fn request(url: &str, referer: &str, opts: &DownloadOptions) -> ureq::Request {
+    let mut request = ureq::get(url).set("Referer", referer);
+    if let Some(user_agent) = &opts.user_agent {
+        request = request.set("User-Agent", user_agent);
+    }
+    for (name, value) in &opts.headers {
+        request = request.set(name, value);
+    }
+    request.timeout(Duration::from_secs(30))
+}
+
+/// 下载失败后可以重试时返回 `Some(服务器要求的等待时间)`：网络错误、HTTP 429 和 5xx 可以重试，
+/// 只有 HTTP 429 会读取 `Retry-After`
+fn retry_after(result: &Result<ureq::Response, ureq::Error>) -> Option<Option<Duration>> {
+    match result {
+        Err(ureq::Error::Status(429, res)) => {
+            Some(res.header("Retry-After").and_then(parse_retry_after))
+        }
+        Err(ureq::Error::Status(500..=599, _) | ureq::Error::Transport(_)) => Some(None),
+        _ => None,
+    }
+}
+
+/// 解析 `Retry-After` 的值：秒数，或 HTTP 日期（如 `Wed, 21 Oct 2015 07:28:00 GMT`）。
+/// 已经过去的日期不需要等待。
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+/// 下载的牌谱的缓存目录：`$XDG_CACHE_HOME`、Windows 上的 `%LOCALAPPDATA%` 或 `~/.cache`
+/// 下的 `tenhou2mjai`。
+///
+/// 不使用所有用户共用的系统临时目录，其他用户无法在其中预先放置伪造的牌谱或符号链接。
+fn cache_dir() -> Result<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .or_else(|| env::var_os("LOCALAPPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .context("cannot determine the cache directory for downloads, set XDG_CACHE_HOME")?;
+    Ok(base.join("tenhou2mjai"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header_splits_name_and_value() {
+        let header = parse_header(" Cookie : sid=a:b ").unwrap();
+        assert_eq!(header, ("Cookie".to_owned(), "sid=a:b".to_owned()));
+        assert!(parse_header("no colon").is_err());
+        assert!(parse_header(": value").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+    }
+
+    #[test]
+    fn parse_retry_after_accepts_seconds_and_dates() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        let later = (Utc::now() + chrono::TimeDelta::hours(1)).to_rfc2822();
+        let wait = parse_retry_after(&later).unwrap();
+        assert!(wait > Duration::from_secs(3500) && wait <= Duration::from_secs(3600), "{wait:?}");
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
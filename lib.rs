@@ -0,0 +1,2958 @@
+//! tenhou.net/6 牌谱到 mjai 事件的转换接口，供其他 Rust 程序直接调用，
+//! 无需通过命令行。
+//!
+//! ```ignore
+//! let events = mjai_reviewer::convert_log(&body)?;
+//! mjai_reviewer::write_events(&mut std::io::stdout().lock(), &events)?;
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::iter;
+use std::mem;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use convlog::mjai::Event;
+use convlog::tenhou::{GameLength, Log, RawLog};
+use convlog::tenhou_to_mjai;
+use serde::{Deserialize, Serialize};
+use serde_json::{self as json, json};
+use unicode_normalization::UnicodeNormalization;
+
+/// 将 tenhou.net/6 格式的 JSON 字符串转换为 mjai 事件。
+pub fn convert_log(raw: &str) -> Result<Vec<Event>> {
+    let mut raw = parse_tenhou_json(raw)?;
+    ensure!(!is_sanma(&raw), SANMA_UNSUPPORTED);
+    normalize_names(&mut raw);
+    let raw_log = RawLog::deserialize(&raw).context("failed to parse tenhou.net/6 log")?;
+    let log = Log::try_from(raw_log).context("invalid log")?;
+    convert_raw_log(&log)
+}
+
+/// 将字符串解析为 JSON，并检查顶层结构是否像 tenhou.net/6 牌谱
+/// （含有 `log`、`rule` 键）。匿名化的牌谱可能没有 `name`，见 [`normalize_names`]。
+///
+/// 用于在完整解析 [`RawLog`] 之前给出明确的错误信息，而不是一大段 serde 报错，
+/// 例如误把已经转换过的 mjai 牌谱当作输入，或者下载中断导致牌谱被截断时。
+pub fn parse_tenhou_json(body: &str) -> Result<json::Value> {
+    let raw = json::from_str(body).map_err(|e| parse_error(body, e))?;
+    check_parsed(raw)
+}
+
+/// 与 [`parse_tenhou_json`] 相同，但直接从 `reader` 流式解析，不需要先把整个牌谱读入内存。
+///
+/// JSON 语法错误时的信息不如 [`parse_tenhou_json`] 详细（不会判断是否为 mjai 牌谱或在哪一局截断），
+/// 需要时可以在出错后读入全部内容，再用 [`parse_tenhou_json`] 解析一次。
+pub fn parse_tenhou_reader<R: Read>(reader: R) -> Result<json::Value> {
+    let raw = json::from_reader(reader).context("failed to parse tenhou.net/6 log")?;
+    check_parsed(raw)
+}
+
+/// 检查解析出的 JSON 的顶层结构，以及每一局是否完整
+fn check_parsed(raw: json::Value) -> Result<json::Value> {
+    check_structure(&raw)?;
+
+    let kyokus = raw["log"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let complete = kyokus.iter().take_while(|kyoku| is_complete_kyoku(kyoku)).count();
+    ensure!(
+        complete == kyokus.len(),
+        "log appears truncated ({complete} of {} kyoku present)",
+        kyokus.len()
+    );
+
+    Ok(raw)
+}
+
+/// 与 [`parse_tenhou_json`] 相同，但牌谱被截断时只保留其中完整的局，而不是报错。
+///
+/// 第二个返回值为截断时的说明，牌谱完整时为 `None`。一个完整的局都没有时仍然报错。
+pub fn parse_tenhou_json_lenient(body: &str) -> Result<(json::Value, Option<String>)> {
+    let (mut raw, mut truncated) = match json::from_str(body) {
+        Ok(raw) => (raw, false),
+        Err(e) if e.is_eof() => {
+            let Some((end, _)) = truncated_log_end(body) else {
+                return Err(parse_error(body, e));
+            };
+            // 补全 `log` 数组和顶层对象的括号
+            let repaired = format!("{}]}}", &body[..end]);
+            let raw = json::from_str(&repaired)
+                .context("failed to parse truncated tenhou.net/6 log")?;
+            (raw, true)
+        }
+        Err(e) => return Err(parse_error(body, e)),
+    };
+    check_structure(&raw)?;
+
+    let kyokus = raw["log"].as_array_mut().context("`log` is not an array")?;
+    let complete = kyokus.iter().take_while(|kyoku| is_complete_kyoku(kyoku)).count();
+    truncated |= complete < kyokus.len();
+    kyokus.truncate(complete);
+    ensure!(complete > 0, "log appears truncated before the first kyoku ended");
+
+    let warning = truncated
+        .then(|| format!("log appears truncated, converting only the {complete} complete kyoku"));
+    Ok((raw, warning))
+}
+
+/// 一个文件中有多场比赛时（顶层为牌谱的数组，或多个牌谱对象首尾相接），返回每场比赛的 JSON。
+///
+/// 只有一场比赛、无法解析或其中有不像牌谱的值（没有 `log`）时返回 `None`，
+/// 这时应由 [`parse_tenhou_json`] 解析并报告错误。每行一个事件的 mjai 牌谱不会被当作多场比赛。
+pub fn split_games(body: &str) -> Option<Vec<json::Value>> {
+    let mut values = json::Deserializer::from_str(body)
+        .into_iter::<json::Value>()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let games = if values.len() == 1 {
+        match values.pop() {
+            Some(json::Value::Array(games)) => games,
+            _ => return None,
+        }
+    } else {
+        values
+    };
+    let all_logs = games.iter().all(|game| game.get("log").is_some());
+    (!games.is_empty() && all_logs).then_some(games)
+}
+
+/// JSON 解析失败时，尽量说明原因
+fn parse_error(body: &str, e: json::Error) -> anyhow::Error {
+    // mjai 牌谱每行一个事件，整体不是合法的 JSON，只能通过第一行判断
+    let first_line = body.lines().next().unwrap_or_default();
+    let looks_like_mjai =
+        json::from_str::<json::Value>(first_line).is_ok_and(|v| v.get("type").is_some());
+    if looks_like_mjai {
+        return anyhow!("this doesn't look like a tenhou.net/6 log, it looks like an mjai log");
+    }
+
+    if e.is_eof() {
+        let complete = truncated_log_end(body).map_or(0, |(_, complete)| complete);
+        return anyhow::Error::new(e).context(format!(
+            "log appears truncated (the JSON ends after {complete} complete kyoku)"
+        ));
+    }
+    anyhow::Error::new(e).context("failed to parse tenhou.net/6 log")
+}
+
+fn check_structure(raw: &json::Value) -> Result<()> {
+    let Some(top) = raw.as_object() else {
+        bail!("this doesn't look like a tenhou.net/6 log: the top level is not a JSON object");
+    };
+    ensure!(
+        !top.contains_key("type"),
+        "this doesn't look like a tenhou.net/6 log, it looks like an mjai event"
+    );
+    for key in ["log", "rule"] {
+        ensure!(
+            top.contains_key(key),
+            "this doesn't look like a tenhou.net/6 log: missing the `{key}` key"
+        );
+    }
+    ensure!(
+        raw["log"].is_array(),
+        "this doesn't look like a tenhou.net/6 log: `log` is not an array"
+    );
+    Ok(())
+}
+
+/// 一局的数组依次为局信息、点数、宝牌、里宝牌、四家各自的配牌/摸牌/舍牌，最后是结果，共 17 项
+fn is_complete_kyoku(kyoku: &json::Value) -> bool {
+    kyoku.as_array().is_some_and(|kyoku| kyoku.len() == 17)
+}
+
+/// 在被截断的牌谱 JSON 中找到最后一个完整的局的结束位置，返回该位置及完整的局数。
+///
+/// 只跟踪括号和字符串，顶层对象中的数组的元素数组（即 `log` 中的各局）结束时记录一次。
+fn truncated_log_end(body: &str) -> Option<(usize, usize)> {
+    let mut stack = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut end = None;
+    let mut complete = 0;
+
+    for (i, b) in body.bytes().enumerate() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => stack.push(b),
+            b'}' | b']' => {
+                stack.pop();
+                if stack == [b'{', b'['] {
+                    end = Some(i + 1);
+                    complete += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    end.map(|end| (end, complete))
+}
+
+pub const SANMA_UNSUPPORTED: &str =
+    "three-player (sanma) logs are not supported, only four-player logs can be converted";
+
+/// 判断 tenhou.net/6 牌谱是否为三人麻将。
+///
+/// 三麻牌谱的规则名（`rule.disp`）中含有“三”，且第四家的配牌为空。
+pub fn is_sanma(raw: &json::Value) -> bool {
+    let disp_is_sanma = raw["rule"]["disp"]
+        .as_str()
+        .is_some_and(|disp| disp.contains('三'));
+    // 每局的数组中第 13 项为北家（第四家）的配牌
+    let fourth_seat_empty = raw["log"]
+        .as_array()
+        .and_then(|kyokus| kyokus.first())
+        .is_some_and(|kyoku| kyoku[13].as_array().is_some_and(Vec::is_empty));
+    disp_is_sanma || fourth_seat_empty
+}
+
+/// 天凤牌谱中途流局的结果名
+const ABORTIVE_DRAWS: [&str; 5] = ["九種九牌", "四家立直", "三家和了", "四槓散了", "四風連打"];
+
+/// 牌谱中是否有中途流局的局，或者打过的局（不计连庄）少于 `game_length` 应有的局数，
+/// 即比赛因有人被飞等原因提前结束
+pub fn is_incomplete_game(raw: &json::Value, game_length: GameLength) -> bool {
+    let kyokus = raw["log"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let abortive = kyokus.iter().any(|kyoku| {
+        let result = kyoku.as_array().and_then(|k| k.last());
+        result.is_some_and(|r| r[0].as_str().is_some_and(|r| ABORTIVE_DRAWS.contains(&r)))
+    });
+
+    // 天凤的局序号 0~3 为东场、4~7 为南场，连庄时不变
+    let mut rounds: Vec<_> = kyokus.iter().filter_map(|kyoku| kyoku[0][0].as_u64()).collect();
+    rounds.dedup();
+    let expected = match game_length {
+        GameLength::Tonpuu => 4,
+        GameLength::Hanchan => 8,
+    };
+    abortive || rounds.len() < expected
+}
+
+/// 整理牌谱中的玩家名：解码 URL 编码（如 `%E3%81%82`）的名字，并统一为 NFC 形式，
+/// 避免转换后的 mjai 牌谱中出现乱码或看起来相同、实际编码不同的名字。
+///
+/// 匿名化的牌谱中缺失、为 `null` 或空字符串的名字按座位替换为 `player0` ~ `player3`，
+/// 使每个座位的名字都不同且稳定。无法解码或类型不对的名字同样替换，并返回相应的警告。
+pub fn normalize_names(raw: &mut json::Value) -> Vec<String> {
+    let mut warnings = vec![];
+    if !raw["name"].is_array() {
+        raw["name"] = json!([]);
+    }
+    let names = raw["name"].as_array_mut().expect("`name` was just made an array");
+    names.resize(4, json::Value::Null);
+
+    for (seat, name) in names.iter_mut().enumerate() {
+        let decoded = match &*name {
+            json::Value::Null => None,
+            json::Value::String(s) if s.is_empty() => None,
+            json::Value::String(s) if is_percent_encoded(s) => {
+                let decoded = percent_decode(s);
+                if decoded.is_none() {
+                    warnings.push(format!("failed to decode the name of seat {seat}: {s:?}"));
+                }
+                decoded
+            }
+            json::Value::String(s) => Some(s.clone()),
+            other => {
+                warnings.push(format!("the name of seat {seat} is not a string: {other}"));
+                None
+            }
+        };
+        let normalized = match decoded {
+            Some(decoded) => decoded.nfc().collect(),
+            None => format!("player{seat}"),
+        };
+        *name = json::Value::String(normalized);
+    }
+    warnings
+}
+
+/// 将牌谱中的玩家名替换为 `player0` ~ `player3`，用于公开分享转换结果。
+///
+/// 指定 `salt` 时改为替换为以 `salt` 为密钥的 BLAKE3 名字哈希（取前 16 位十六进制数），
+/// 同一玩家在不同牌谱中的名字相同，仍然可以按玩家统计。不知道 `salt` 就无法由哈希反推名字，
+/// 因此不能使用 [`ContentHasher`] 这样可逆的非密码学哈希。应在 [`normalize_names`] 之后调用。
+pub fn anonymize_names(raw: &mut json::Value, salt: Option<&str>) {
+    let Some(names) = raw["name"].as_array_mut() else {
+        return;
+    };
+    // 任意长度的盐先派生为 32 字节的密钥
+    let key = salt.map(|salt| blake3::derive_key("tenhou2mjai anonymize_names", salt.as_bytes()));
+    for (seat, name) in names.iter_mut().enumerate() {
+        let anonymized = match &key {
+            Some(key) => {
+                let hash = blake3::keyed_hash(key, name.as_str().unwrap_or_default().as_bytes());
+                hash.to_hex()[..16].to_owned()
+            }
+            None => format!("player{seat}"),
+        };
+        *name = json::Value::String(anonymized);
+    }
+}
+
+/// 含有 `%` 且每个 `%` 后都是两位十六进制数
+fn is_percent_encoded(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    s.contains('%')
+        && bytes.iter().enumerate().filter(|&(_, &b)| b == b'%').all(|(i, _)| {
+            bytes.get(i + 1..i + 3).is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        })
+}
+
+/// 解码 URL 编码的 UTF-8 字符串，结果不是合法的 UTF-8 时返回 `None`
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// 将已解析的 [`Log`] 转换为 mjai 事件。
+pub fn convert_raw_log(log: &Log) -> Result<Vec<Event>> {
+    Ok(tenhou_to_mjai(log)?)
+}
+
+/// 将 mjai 事件以 NDJSON 格式（每行一个事件）写入 `writer`，写完后 flush。
+///
+/// `events` 通常是 [`Event`]，也可以是加入了额外字段的 JSON。
+pub fn write_events<W: Write, E: Serialize>(writer: &mut W, events: &[E]) -> Result<()> {
+    for (i, event) in events.iter().enumerate() {
+        json::to_writer(&mut *writer, event)
+            .and_then(|_| writer.write_all(b"\n").map_err(json::Error::io))
+            .with_context(|| format!("failed to write event #{i}"))?;
+    }
+    writer.flush().context("failed to flush output")
+}
+
+/// 解析 NDJSON 格式（每行一个事件）的 mjai 牌谱，空行会被忽略。
+pub fn parse_mjai_events(body: &str) -> Result<Vec<Event>> {
+    body.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            json::from_str(line).with_context(|| format!("invalid mjai event on line {}", i + 1))
+        })
+        .collect()
+}
+
+/// 将 mjai 事件写为单个 JSON 数组（每行一个元素），写完后 flush。
+///
+/// 逐个事件序列化后直接写入 `writer`，不会先拼接成完整的字符串。
+pub fn write_events_array<W: Write, E: Serialize>(writer: &mut W, events: &[E]) -> Result<()> {
+    writer.write_all(b"[").context("failed to write array start")?;
+    for (i, event) in events.iter().enumerate() {
+        let separator: &[u8] = if i == 0 { b"\n" } else { b",\n" };
+        writer
+            .write_all(separator)
+            .map_err(json::Error::io)
+            .and_then(|_| json::to_writer(&mut *writer, event))
+            .with_context(|| format!("failed to write event #{i}"))?;
+    }
+    writer.write_all(b"\n]\n").context("failed to write array end")?;
+    writer.flush().context("failed to flush output")
+}
+
+/// 将每局的最终手牌（见 [`final_hands`]）以 NDJSON 格式（每行一局）写入 `writer`，写完后 flush。
+pub fn write_final_hands<W: Write>(writer: &mut W, events: &[Event]) -> Result<()> {
+    for (i, record) in final_hands(events)?.iter().enumerate() {
+        json::to_writer(&mut *writer, record)
+            .and_then(|_| writer.write_all(b"\n").map_err(json::Error::io))
+            .with_context(|| format!("failed to write the hands of kyoku #{i}"))?;
+    }
+    writer.flush().context("failed to flush output")
+}
+
+/// 将 mjai 事件写为便于阅读的逐巡记录（如 `East (A) draws 3m, discards 9p`），写完后 flush。
+///
+/// 每局开始时列出点数和配牌，结束时按 [`final_hands`] 的重放结果列出和了或流局的点数变动，
+/// 以及各家的手牌与副露，用于快速检查转换结果是否合理。
+pub fn write_transcript<W: Write>(writer: &mut W, events: &[Event]) -> Result<()> {
+    let records = final_hands(events)?;
+    let mut transcript = Transcript::default();
+    let mut kyoku_index = 0;
+    for (i, event) in event_values(events)?.iter().enumerate() {
+        let kind = event["type"].as_str().unwrap_or_default();
+        let actor = event["actor"].as_u64().map(|a| a as usize).filter(|&a| a < 4);
+        let actor = || actor.with_context(|| format!("event #{i}: {kind} without a valid actor"));
+        let pai = transcript_tile(&event["pai"]);
+        match kind {
+            "start_game" => {
+                let names = event["names"].as_array().map(Vec::as_slice).unwrap_or_default();
+                let names = names.iter().map(|name| name.as_str().unwrap_or_default().to_owned());
+                transcript.names = names.collect();
+            }
+            "start_kyoku" => transcript.start_kyoku(event),
+            "tsumo" => {
+                transcript.flush();
+                transcript.act(actor()?, &format!("draws {pai}"));
+            }
+            "reach" => transcript.act(actor()?, "declares riichi"),
+            "dahai" => {
+                let tsumogiri = if event["tsumogiri"] == true { " (tsumogiri)" } else { "" };
+                transcript.act(actor()?, &format!("discards {pai}{tsumogiri}"));
+                transcript.flush();
+            }
+            "chi" | "pon" | "daiminkan" => {
+                transcript.flush();
+                let target = event["target"].as_u64().map_or(0, |t| t as usize % 4);
+                let action = format!("calls {kind} on {pai} from {} with {}",
+                                     transcript.seat(target),
+                                     transcript_tiles(&event["consumed"]));
+                transcript.act(actor()?, &action);
+            }
+            "kakan" => {
+                transcript.act(actor()?, &format!("adds {pai} to a pon (kakan)"));
+                transcript.flush();
+            }
+            "ankan" => {
+                let consumed = transcript_tiles(&event["consumed"]);
+                transcript.act(actor()?, &format!("declares ankan with {consumed}"));
+                transcript.flush();
+            }
+            "dora" => {
+                transcript.flush();
+                let marker = transcript_tile(&event["dora_marker"]);
+                transcript.push(format!("  new dora indicator {marker}"));
+            }
+            "end_kyoku" => {
+                transcript.flush();
+                let record = records
+                    .get(kyoku_index)
+                    .with_context(|| format!("event #{i}: end_kyoku without a replayed kyoku"))?;
+                kyoku_index += 1;
+                transcript.end_kyoku(record);
+            }
+            _ => (),
+        }
+    }
+    transcript.flush();
+
+    writer.write_all(transcript.out.as_bytes()).context("failed to write transcript")?;
+    writer.flush().context("failed to flush output")
+}
+
+/// [`write_transcript`] 生成中的记录
+#[derive(Default)]
+struct Transcript {
+    names: Vec<String>,
+    /// 当局庄家的座位
+    oya: usize,
+    /// 尚未写出的一行，同一家连续的动作（摸牌、立直、打牌）写在同一行
+    line: String,
+    out: String,
+}
+
+impl Transcript {
+    /// 座位按当局的自风显示，有玩家名时附在后面
+    fn seat(&self, seat: usize) -> String {
+        const WINDS: [&str; 4] = ["East", "South", "West", "North"];
+
+        let wind = WINDS[(seat + 4 - self.oya) % 4];
+        match self.names.get(seat).filter(|name| !name.is_empty()) {
+            Some(name) => format!("{wind} ({name})"),
+            None => wind.to_owned(),
+        }
+    }
+
+    fn act(&mut self, actor: usize, action: &str) {
+        if self.line.is_empty() {
+            self.line = self.seat(actor);
+        } else {
+            self.line.push(',');
+        }
+        self.line.push(' ');
+        self.line.push_str(action);
+    }
+
+    fn push(&mut self, line: String) {
+        self.out.push_str(&line);
+        self.out.push('\n');
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = mem::take(&mut self.line);
+            self.push(line);
+        }
+    }
+
+    fn start_kyoku(&mut self, event: &json::Value) {
+        self.oya = event["oya"].as_u64().map_or(0, |oya| oya as usize % 4);
+        let bakaze = match event["bakaze"].as_str() {
+            Some("S") => "South",
+            Some("W") => "West",
+            Some("N") => "North",
+            _ => "East",
+        };
+        let dora = transcript_tile(&event["dora_marker"]);
+        self.push(format!("{bakaze} {}, honba {}, riichi sticks {}, dora indicator {dora}",
+                          event["kyoku"],
+                          event["honba"],
+                          event["kyotaku"]));
+        for seat in 0..4 {
+            let tehai = transcript_tiles(&event["tehais"][seat]);
+            let line = format!("  {}: {} points, {tehai}", self.seat(seat), event["scores"][seat]);
+            self.push(line);
+        }
+    }
+
+    /// 写出 [`final_hands`] 中一局的结果，以及各家的手牌与副露
+    fn end_kyoku(&mut self, record: &json::Value) {
+        let seat_of = |value: &json::Value| value.as_u64().map_or(0, |s| s as usize % 4);
+        for result in record["result"].as_array().map(Vec::as_slice).unwrap_or_default() {
+            let deltas = match result["deltas"].as_array() {
+                Some(deltas) => {
+                    let deltas = deltas.iter().map(|d| d.as_i64().unwrap_or_default());
+                    let deltas = deltas.map(|delta| format!("{delta:+}"));
+                    format!("deltas {}", deltas.collect::<Vec<_>>().join(" "))
+                }
+                None => "no score change".to_owned(),
+            };
+            let line = if result["type"] == "hora" {
+                let (actor, target) = (seat_of(&result["actor"]), seat_of(&result["target"]));
+                let by = if actor == target {
+                    "tsumo".to_owned()
+                } else {
+                    format!("ron from {}", self.seat(target))
+                };
+                let pai = transcript_tile(&result["pai"]);
+                format!("{} wins by {by} on {pai}, {deltas}", self.seat(actor))
+            } else {
+                format!("Draw, {deltas}")
+            };
+            self.push(line);
+        }
+        for seat in 0..4 {
+            let hand = &record["hands"][seat];
+            let melds = hand["melds"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let melds: String = melds
+                .iter()
+                .map(|meld| {
+                    // 暗杠没有鸣入的牌
+                    let consumed = meld["consumed"].as_array().map(Vec::as_slice);
+                    let tiles: Vec<_> = iter::once(&meld["pai"])
+                        .filter(|pai| !pai.is_null())
+                        .chain(consumed.unwrap_or_default())
+                        .cloned()
+                        .collect();
+                    let kind = meld["type"].as_str().unwrap_or_default();
+                    format!(" [{kind} {}]", transcript_tiles(&json!(tiles)))
+                })
+                .collect();
+            let tehai = transcript_tiles(&hand["tehai"]);
+            let line = format!("  {}: {tehai}{melds}", self.seat(seat));
+            self.push(line);
+        }
+        self.out.push('\n');
+    }
+}
+
+/// mjai 格式的牌，缺失时为 `?`
+fn transcript_tile(tile: &json::Value) -> &str {
+    tile.as_str().unwrap_or("?")
+}
+
+/// 按万、筒、索、字牌排序后以空格分隔的牌
+fn transcript_tiles(tiles: &json::Value) -> String {
+    let tiles = tiles.as_array().map(Vec::as_slice).unwrap_or_default();
+    let mut tiles: Vec<_> = tiles.iter().map(transcript_tile).collect();
+    tiles.sort_by_key(|tile| tile_sort_key(tile));
+    tiles.join(" ")
+}
+
+/// 重放 mjai 事件，返回每局结束（和了或流局）时四家的手牌与副露。
+///
+/// 每局一条记录，包含 `bakaze`、`kyoku`、`honba`、`hands`（每家的 `tehai` 与 `melds`）
+/// 以及 `result`。`result` 中 `hora` 的 `pai` 为和了牌，自摸时和了牌不计入 `tehai`；
+/// 一炮多响时每个和了都各有一项。
+pub fn final_hands(events: &[Event]) -> Result<Vec<json::Value>> {
+    let mut records = vec![];
+    let mut header = json::Value::Null;
+    let mut tehais: [Vec<String>; 4] = Default::default();
+    let mut melds: [Vec<json::Value>; 4] = Default::default();
+    let mut last_tile = json::Value::Null;
+    let mut results = vec![];
+
+    for (i, event) in event_values(events)?.into_iter().enumerate() {
+        let actor = event_seat(i, &event, "actor")?;
+        let tile = |value: &json::Value| value.as_str().unwrap_or_default().to_owned();
+        let mut take =
+            |actor: usize, tiles: &[&json::Value]| remove_tiles(i, &mut tehais[actor], tiles);
+
+        match (event["type"].as_str(), actor) {
+            (Some("start_kyoku"), _) => {
+                header = json!({
+                    "bakaze": event["bakaze"],
+                    "kyoku": event["kyoku"],
+                    "honba": event["honba"],
+                });
+                for (seat, tehai) in tehais.iter_mut().enumerate() {
+                    let tiles = event["tehais"][seat].as_array().map(Vec::as_slice);
+                    *tehai = tiles.unwrap_or_default().iter().map(tile).collect();
+                }
+                melds = Default::default();
+                results.clear();
+            }
+            (Some("tsumo"), Some(actor)) => {
+                tehais[actor].push(tile(&event["pai"]));
+                last_tile = event["pai"].clone();
+            }
+            (Some("dahai"), Some(actor)) => {
+                take(actor, &[&event["pai"]])?;
+                last_tile = event["pai"].clone();
+            }
+            (Some(kind @ ("chi" | "pon" | "daiminkan")), Some(actor)) => {
+                let consumed = event["consumed"].as_array().map(Vec::as_slice);
+                take(actor, &consumed.unwrap_or_default().iter().collect::<Vec<_>>())?;
+                melds[actor].push(json!({
+                    "type": kind,
+                    "target": event["target"],
+                    "pai": event["pai"],
+                    "consumed": event["consumed"],
+                }));
+            }
+            (Some("kakan"), Some(actor)) => {
+                take(actor, &[&event["pai"]])?;
+                last_tile = event["pai"].clone();
+                // 加杠的牌与之前碰的牌只可能在是否为赤宝牌上不同
+                let deaka = |t: &json::Value| tile(t).trim_end_matches('r').to_owned();
+                let pai = deaka(&event["pai"]);
+                let pon = melds[actor]
+                    .iter_mut()
+                    .find(|m| m["type"] == "pon" && deaka(&m["pai"]) == pai)
+                    .with_context(|| format!("event #{i}: kakan without a matching pon"))?;
+                *pon = json!({
+                    "type": "kakan",
+                    "target": pon["target"],
+                    "pai": event["pai"],
+                    "consumed": event["consumed"],
+                });
+            }
+            (Some("ankan"), Some(actor)) => {
+                let consumed = event["consumed"].as_array().map(Vec::as_slice);
+                take(actor, &consumed.unwrap_or_default().iter().collect::<Vec<_>>())?;
+                melds[actor].push(json!({ "type": "ankan", "consumed": event["consumed"] }));
+            }
+            (Some("hora"), Some(actor)) => {
+                // 自摸时和了牌已在手牌中，与荣和时一样不计入 `tehai`
+                let target = event_seat(i, &event, "target")?.unwrap_or(actor);
+                if target == actor {
+                    take(actor, &[&last_tile])?;
+                }
+                results.push(json!({
+                    "type": "hora",
+                    "actor": actor,
+                    "target": target,
+                    "pai": last_tile,
+                    "deltas": event["deltas"],
+                }));
+            }
+            (Some("ryukyoku"), _) => {
+                results.push(json!({ "type": "ryukyoku", "deltas": event["deltas"] }));
+            }
+            (Some("end_kyoku"), _) => {
+                let mut record = header.take();
+                record["hands"] = tehais
+                    .iter_mut()
+                    .zip(&melds)
+                    .map(|(tehai, melds)| {
+                        tehai.sort_by_key(|t| tile_sort_key(t));
+                        json!({ "tehai": tehai, "melds": melds })
+                    })
+                    .collect();
+                record["result"] = json!(results);
+                records.push(record);
+            }
+            _ => (),
+        }
+    }
+    Ok(records)
+}
+
+/// 从手牌中去掉第 `i` 个事件打出或鸣牌用掉的 `tiles`，不在手牌中时返回错误
+fn remove_tiles(i: usize, tehai: &mut Vec<String>, tiles: &[&json::Value]) -> Result<()> {
+    for &pai in tiles {
+        let pai = pai.as_str().unwrap_or_default();
+        let pos = tehai.iter().position(|t| t == pai);
+        let pos = pos.with_context(|| format!("event #{i}: {pai} not in hand"))?;
+        tehai.remove(pos);
+    }
+    Ok(())
+}
+
+/// 重放 mjai 事件，对每次打牌比较实际的打法与进张最多的打法（见 [`discard_ukeire`]）。
+///
+/// 立直后的打牌不是选择，不计入。每次打牌一条记录，包含 `bakaze`、`kyoku`、`honba`、`actor`、
+/// `turn`（该家本局中的摸牌次数）、`hand`（打牌前的手牌）、`discard`、`tsumogiri`、
+/// `actual`（实际打出后的 [`Ukeire`]）、`best`（最好的打法打出后的 [`Ukeire`]）和
+/// `best_discards`（所有同样好的打法）。
+pub fn efficiency_decisions(events: &[Event]) -> Result<Vec<json::Value>> {
+    let mut records = vec![];
+    let mut header = json::Value::Null;
+    let mut tehais: [Vec<String>; 4] = Default::default();
+    let mut turns = [0; 4];
+    let mut in_riichi = [false; 4];
+
+    for (i, event) in event_values(events)?.iter().enumerate() {
+        let actor = event_seat(i, event, "actor")?;
+        let consumed = event["consumed"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let consumed: Vec<_> = consumed.iter().collect();
+        match (event["type"].as_str(), actor) {
+            (Some("start_kyoku"), _) => {
+                header = json!({
+                    "bakaze": event["bakaze"],
+                    "kyoku": event["kyoku"],
+                    "honba": event["honba"],
+                });
+                for (seat, tehai) in tehais.iter_mut().enumerate() {
+                    let tiles = event["tehais"][seat].as_array().map(Vec::as_slice);
+                    let tiles = tiles.unwrap_or_default().iter();
+                    *tehai = tiles.map(|t| t.as_str().unwrap_or_default().to_owned()).collect();
+                }
+                turns = [0; 4];
+                in_riichi = [false; 4];
+            }
+            (Some("tsumo"), Some(actor)) => {
+                tehais[actor].push(event["pai"].as_str().unwrap_or_default().to_owned());
+                turns[actor] += 1;
+            }
+            (Some("reach_accepted"), Some(actor)) => in_riichi[actor] = true,
+            (Some("dahai"), Some(actor)) => {
+                if !in_riichi[actor] {
+                    let options = discard_ukeire(&tehais[actor])
+                        .with_context(|| format!("event #{i}: cannot evaluate the hand"))?;
+                    let discard = event["pai"].as_str().unwrap_or_default();
+                    let plain = discard.trim_end_matches('r');
+                    let (_, actual) = options
+                        .iter()
+                        .find(|(tile, _)| tile == plain)
+                        .with_context(|| format!("event #{i}: {discard} not in hand"))?;
+                    let (_, best) = &options[0];
+                    let best_discards: Vec<_> = options
+                        .iter()
+                        .filter(|(_, u)| (u.shanten, u.count) == (best.shanten, best.count))
+                        .map(|(tile, _)| tile)
+                        .collect();
+                    let mut hand = tehais[actor].clone();
+                    hand.sort_by_key(|t| tile_sort_key(t));
+
+                    let mut record = header.clone();
+                    record["actor"] = json!(actor);
+                    record["turn"] = json!(turns[actor]);
+                    record["hand"] = json!(hand);
+                    record["discard"] = json!(discard);
+                    record["tsumogiri"] = event["tsumogiri"].clone();
+                    record["actual"] = json!(actual);
+                    record["best"] = json!(best);
+                    record["best_discards"] = json!(best_discards);
+                    records.push(record);
+                }
+                remove_tiles(i, &mut tehais[actor], &[&event["pai"]])?;
+            }
+            (Some("chi" | "pon" | "daiminkan" | "ankan"), Some(actor)) => {
+                remove_tiles(i, &mut tehais[actor], &consumed)?;
+            }
+            (Some("kakan"), Some(actor)) => remove_tiles(i, &mut tehais[actor], &[&event["pai"]])?,
+            _ => (),
+        }
+    }
+    Ok(records)
+}
+
+/// 第 `i` 个事件 `event` 中的座位字段（`actor` 或 `target`），没有该字段时为 `None`，
+/// 不是 0~3 的座位时返回错误
+fn event_seat(i: usize, event: &json::Value, field: &str) -> Result<Option<usize>> {
+    let value = &event[field];
+    if value.is_null() {
+        return Ok(None);
+    }
+    let seat = value.as_u64().map(|s| s as usize).filter(|&s| s < 4);
+    let kind = event["type"].as_str().unwrap_or_default();
+    let seat = seat.with_context(|| format!("event #{i}: {kind} with invalid {field} {value}"))?;
+    Ok(Some(seat))
+}
+
+/// mjai 格式的牌按万、筒、索、字牌排序的键，赤五排在普通的五之后
+fn tile_sort_key(tile: &str) -> (usize, usize, bool) {
+    const HONORS: [&str; 7] = ["E", "S", "W", "N", "P", "F", "C"];
+
+    if let Some(n) = HONORS.iter().position(|&h| h == tile) {
+        return (3, n, false);
+    }
+    let mut chars = tile.chars();
+    let number = chars.next().and_then(|c| c.to_digit(10)).unwrap_or_default() as usize;
+    let suit = chars.next().and_then(|c| "mps".find(c)).unwrap_or(4);
+    (suit, number, tile.ends_with('r'))
+}
+
+/// 转换结果与原始牌谱不一致的地方
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// 出现不一致的局在牌谱中的序号（从 0 开始），`None` 表示整场比赛层面的不一致
+    pub kyoku_index: Option<usize>,
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(index) = self.kyoku_index {
+            write!(f, "kyoku #{index}: ")?;
+        }
+        write!(f, "{} expected {}, got {}", self.field, self.expected, self.actual)
+    }
+}
+
+/// 将转换得到的 mjai 事件与原始 tenhou.net/6 牌谱（`raw`）比对。
+///
+/// 比对的内容包括局数、每局的场风/局数/本场/供托、开局点数、宝牌指示牌、
+/// 里宝牌指示牌、每次和了和流局的点数变动、每局结束时各家的手牌和副露数
+/// （mjai 一侧见 [`final_hands`]，天凤一侧按配牌、摸牌和打牌重放），
+/// 以及牌谱 `sc` 中的最终得点。返回所有不一致之处，完全一致时返回空列表。
+pub fn verify_events(raw: &json::Value, events: &[Event]) -> Result<Vec<Mismatch>> {
+    let raw_kyokus = raw["log"]
+        .as_array()
+        .context("tenhou.net/6 log has no `log` array")?;
+    let hands = final_hands(events)?;
+    let events = event_values(events)?;
+
+    // 按 start_kyoku 将事件切分为各局
+    let mut kyokus: Vec<Vec<&json::Value>> = vec![];
+    for event in &events {
+        if event["type"] == "start_kyoku" {
+            kyokus.push(vec![event]);
+        } else if let Some(kyoku) = kyokus.last_mut() {
+            kyoku.push(event);
+        }
+    }
+
+    let mut mismatches = vec![];
+    if kyokus.len() != raw_kyokus.len() {
+        mismatches.push(Mismatch {
+            kyoku_index: None,
+            field: "number of kyoku",
+            expected: raw_kyokus.len().to_string(),
+            actual: kyokus.len().to_string(),
+        });
+    }
+
+    for (i, (raw_kyoku, kyoku)) in raw_kyokus.iter().zip(&kyokus).enumerate() {
+        let mut check = |field: &'static str, expected: json::Value, actual: json::Value| {
+            if expected != actual {
+                mismatches.push(Mismatch {
+                    kyoku_index: Some(i),
+                    field,
+                    expected: expected.to_string(),
+                    actual: actual.to_string(),
+                });
+            }
+        };
+
+        // 天凤的局序号 0~3 为东场、4~7 为南场，依此类推
+        let start = kyoku[0];
+        let meta = &raw_kyoku[0];
+        let kyoku_num = meta[0].as_u64().unwrap_or_default();
+        let bakaze = ["E", "S", "W", "N"][(kyoku_num / 4 % 4) as usize];
+        check("bakaze", json!(bakaze), start["bakaze"].clone());
+        check("kyoku", json!(kyoku_num % 4 + 1), start["kyoku"].clone());
+        check("honba", meta[1].clone(), start["honba"].clone());
+        check("kyotaku", meta[2].clone(), start["kyotaku"].clone());
+        check("scores", raw_kyoku[1].clone(), start["scores"].clone());
+
+        let expected_dora = tenhou_tiles_to_mjai(&raw_kyoku[2]);
+        let actual_dora = iter::once(&start["dora_marker"])
+            .chain(kyoku.iter().filter(|e| e["type"] == "dora").map(|e| &e["dora_marker"]))
+            .cloned()
+            .collect();
+        check("dora markers", expected_dora, actual_dora);
+
+        let expected_ura = tenhou_tiles_to_mjai(&raw_kyoku[3]);
+        for hora in kyoku.iter().filter(|e| e["type"] == "hora") {
+            // 未立直的和了不会公开里宝牌
+            let ura = &hora["ura_markers"];
+            if ura.as_array().is_some_and(|ura| !ura.is_empty()) {
+                check("ura markers", expected_ura.clone(), ura.clone());
+            }
+        }
+
+        // 结果数组形如 ["和了", 点数变动, 和了信息, 点数变动, 和了信息, ...]
+        // 或 ["流局", 点数变动]，奇数位置上是点数变动；没有点数变动时视为全 0
+        let result = raw_kyoku
+            .as_array()
+            .and_then(|a| a.last())
+            .and_then(json::Value::as_array);
+        let expected_deltas: Vec<_> = match result {
+            Some(result) if result.len() > 1 => result.iter().skip(1).step_by(2).cloned().collect(),
+            _ => vec![json!([0, 0, 0, 0])],
+        };
+        let actual_deltas: Vec<_> = kyoku
+            .iter()
+            .filter(|e| e["type"] == "hora" || e["type"] == "ryukyoku")
+            .map(|e| match &e["deltas"] {
+                json::Value::Null => json!([0, 0, 0, 0]),
+                deltas => deltas.clone(),
+            })
+            .collect();
+        check("score deltas", json!(expected_deltas), json!(actual_deltas));
+
+        // 结果数组中的和了信息为 [和了者, 放铳者, 包牌者, ...]，和了者与放铳者相同时为自摸
+        let agari = result
+            .filter(|r| r.first().is_some_and(|kind| kind == "和了"))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let tsumo_winners: Vec<_> = agari
+            .iter()
+            .skip(2)
+            .step_by(2)
+            .filter_map(json::Value::as_array)
+            .filter(|info| info.len() >= 2 && info[0] == info[1])
+            .filter_map(|info| info[0].as_u64())
+            .collect();
+        let Some(record) = hands.get(i) else {
+            continue;
+        };
+        for seat in 0..4 {
+            let [haipai, takes, discards] = [4, 5, 6].map(|k| &raw_kyoku[k + seat * 3]);
+            let tsumo = tsumo_winners.contains(&(seat as u64));
+            let replayed = replay_tenhou_hand(haipai, takes, discards, tsumo);
+            // 天凤牌谱本身无法重放时不比对
+            let Some((mut expected, melds)) = replayed else {
+                continue;
+            };
+            expected.sort_by_key(|t| tile_sort_key(t));
+            let hand = &record["hands"][seat];
+            let meld_count = hand["melds"].as_array().map_or(0, Vec::len);
+            check("final hand", json!(expected), hand["tehai"].clone());
+            check("melds", json!(melds), json!(meld_count));
+        }
+    }
+
+    if let (Some(sc), Some(last)) = (raw["sc"].as_array(), kyokus.last()) {
+        let expected: Vec<_> = sc
+            .iter()
+            .step_by(2)
+            .map(|score| (score.as_f64().unwrap_or_default() * 100.0).round() as i64)
+            .collect();
+        let actual = final_scores(last);
+        if json!(expected) != json!(actual) {
+            mismatches.push(Mismatch {
+                kyoku_index: None,
+                field: "final scores",
+                expected: json!(expected).to_string(),
+                actual: json!(actual).to_string(),
+            });
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// 最后一局的事件结束后各家的点数：开局点数减去立直棒，加上和了和流局的点数变动。
+/// 流局结束时剩下的供托归一位（同分时为座位靠前者），与天凤的 `sc` 一致。
+fn final_scores(last_kyoku: &[&json::Value]) -> Vec<i64> {
+    let start = last_kyoku[0];
+    let mut scores: Vec<_> =
+        (0..4).map(|s| start["scores"][s].as_i64().unwrap_or_default()).collect();
+    let mut kyotaku = start["kyotaku"].as_i64().unwrap_or_default();
+    for event in last_kyoku {
+        let deltas = event["deltas"].as_array().map(Vec::as_slice).unwrap_or_default();
+        match event["type"].as_str() {
+            Some("reach_accepted") => {
+                if let Some(actor) = event["actor"].as_u64() {
+                    scores[actor as usize] -= 1000;
+                    kyotaku += 1;
+                }
+            }
+            Some(kind @ ("hora" | "ryukyoku")) => {
+                for (score, delta) in scores.iter_mut().zip(deltas) {
+                    *score += delta.as_i64().unwrap_or_default();
+                }
+                // 和了者已经取得了供托
+                if kind == "hora" {
+                    kyotaku = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+    if let Some(top) = (0..4).max_by_key(|&s| (scores[s], Reverse(s))) {
+        scores[top] += kyotaku * 1000;
+    }
+    scores
+}
+
+/// 按天凤牌谱中一家的配牌、摸牌（`takes`）和打牌（`discards`）重放手牌，
+/// 返回一局结束时的手牌（mjai 格式，未排序）和副露数。无法重放时返回 `None`。
+///
+/// 摸牌中的字符串为吃、碰、大明杠，打牌中的字符串为立直宣言牌、加杠和暗杠，
+/// `60` 为摸切，`0` 为大明杠后不打牌。与 [`final_hands`] 一致，自摸和了（`tsumo`）时
+/// 最后摸到的和了牌不计入手牌。
+fn replay_tenhou_hand(
+    haipai: &json::Value,
+    takes: &json::Value,
+    discards: &json::Value,
+    tsumo: bool,
+) -> Option<(Vec<String>, usize)> {
+    let mut hand: Vec<u64> =
+        haipai.as_array()?.iter().map(json::Value::as_u64).collect::<Option<_>>()?;
+    let takes = takes.as_array()?;
+    let discards = discards.as_array()?;
+    let mut melds = 0;
+    let mut drawn = None;
+
+    let remove = |hand: &mut Vec<u64>, tile: u64| -> Option<()> {
+        let pos = hand.iter().position(|&t| t == tile)?;
+        hand.remove(pos);
+        Some(())
+    };
+    for (i, take) in takes.iter().enumerate() {
+        match take {
+            json::Value::Number(tile) => {
+                let tile = tile.as_u64()?;
+                hand.push(tile);
+                drawn = Some(tile);
+            }
+            json::Value::String(meld) => {
+                // 吃、碰、大明杠：字母后的牌是鸣入的牌，其余的牌来自手牌
+                let (_, tiles, called) = parse_tenhou_meld(meld)?;
+                for (j, &tile) in tiles.iter().enumerate() {
+                    if j != called {
+                        remove(&mut hand, tile)?;
+                    }
+                }
+                melds += 1;
+                drawn = None;
+            }
+            _ => return None,
+        }
+
+        let Some(discard) = discards.get(i) else {
+            if tsumo && i + 1 == takes.len() {
+                remove(&mut hand, drawn?)?;
+            }
+            continue;
+        };
+        match discard {
+            json::Value::Number(tile) => match tile.as_u64()? {
+                0 => (),
+                60 => remove(&mut hand, drawn?)?,
+                tile => remove(&mut hand, tile)?,
+            },
+            json::Value::String(s) if s.starts_with('r') => match s[1..].parse().ok()? {
+                60 => remove(&mut hand, drawn?)?,
+                tile => remove(&mut hand, tile)?,
+            },
+            json::Value::String(meld) => {
+                let (kind, tiles, called) = parse_tenhou_meld(meld)?;
+                match kind {
+                    // 加杠只从手牌中拿出加上的一张，副露数不变
+                    'k' => remove(&mut hand, tiles[called])?,
+                    'a' => {
+                        for tile in tiles {
+                            remove(&mut hand, tile)?;
+                        }
+                        melds += 1;
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    let hand = hand.into_iter().map(tenhou_tile_to_mjai).collect();
+    Some((hand, melds))
+}
+
+/// 解析天凤牌谱中的副露字符串（如 `c275226`、`41p4141`、`424242a42`），
+/// 返回表示种类的字母、按顺序排列的各张牌，以及字母之后那张牌的下标
+fn parse_tenhou_meld(meld: &str) -> Option<(char, Vec<u64>, usize)> {
+    let mut kind = None;
+    let mut tiles = vec![];
+    let mut called = 0;
+    let mut rest = meld;
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphabetic() {
+            kind = Some(c);
+            called = tiles.len();
+            rest = &rest[1..];
+        } else {
+            tiles.push(rest.get(..2)?.parse().ok()?);
+            rest = &rest[2..];
+        }
+    }
+    (called < tiles.len()).then_some((kind?, tiles, called))
+}
+
+/// 将一场四人麻将的 mjai 事件转换回 tenhou.net/6 格式的牌谱，例如用天凤的牌谱查看器回放
+/// 自战生成的 mjai 牌谱。支持 convlog 输出的常见事件，遇到其他事件（如三麻的拔北）时返回错误。
+///
+/// mjai 事件不含符数、番数、役种和流局的种类，因此和了信息只有 `[和了者, 放铳者, 包牌者]`
+/// （包牌者总是和了者本人），流局一律记为 `流局`；规则按有无赤宝牌和是否进入南场推断，
+/// 也不生成需要顺位点的 `sc`。
+pub fn mjai_to_tenhou(events: &[Event]) -> Result<json::Value> {
+    let mut names = json!(["", "", "", ""]);
+    let mut started = false;
+    let mut aka = None;
+    let mut hanchan = false;
+    let mut log = vec![];
+    let mut current: Option<TenhouKyoku> = None;
+    let deltas = |event: &json::Value| match &event["deltas"] {
+        json::Value::Null => json!([0, 0, 0, 0]),
+        deltas => deltas.clone(),
+    };
+
+    for (i, event) in event_values(events)?.iter().enumerate() {
+        let kind = event["type"].as_str().unwrap_or_default();
+        let tile = |value: &json::Value| {
+            mjai_tile_to_tenhou(value).with_context(|| format!("event #{i}: invalid tile {value}"))
+        };
+        let tiles = |value: &json::Value| -> Result<Vec<u64>> {
+            value.as_array().map(Vec::as_slice).unwrap_or_default().iter().map(tile).collect()
+        };
+        match kind {
+            "start_game" => {
+                ensure!(!started, "event #{i}: only a single game can be converted");
+                started = true;
+                if let Some(game_names) = event["names"].as_array() {
+                    ensure!(game_names.len() == 4,
+                            "event #{i}: only four-player games are supported");
+                    names = event["names"].clone();
+                }
+                aka = event["aka_flag"].as_bool();
+                continue;
+            }
+            "start_kyoku" => {
+                let bakaze = ["E", "S", "W", "N"]
+                    .iter()
+                    .position(|&wind| event["bakaze"] == wind)
+                    .with_context(|| format!("event #{i}: invalid bakaze {}", event["bakaze"]))?;
+                let number = event["kyoku"]
+                    .as_u64()
+                    .filter(|kyoku| (1..=4).contains(kyoku))
+                    .with_context(|| format!("event #{i}: invalid kyoku {}", event["kyoku"]))?;
+                hanchan |= bakaze > 0;
+                let mut haipai: [Vec<u64>; 4] = Default::default();
+                for (seat, hand) in haipai.iter_mut().enumerate() {
+                    *hand = tiles(&event["tehais"][seat])?;
+                }
+                current = Some(TenhouKyoku {
+                    meta: json!([bakaze as u64 * 4 + number - 1, event["honba"], event["kyotaku"]]),
+                    scores: event["scores"].clone(),
+                    dora: vec![tile(&event["dora_marker"])?],
+                    haipai,
+                    ..Default::default()
+                });
+                continue;
+            }
+            "end_game" => continue,
+            _ => (),
+        }
+
+        let kyoku = current
+            .as_mut()
+            .with_context(|| format!("event #{i}: {kind} outside of a kyoku"))?;
+        let actor = event["actor"].as_u64().map(|a| a as usize).filter(|&a| a < 4);
+        let actor = || actor.with_context(|| format!("event #{i}: {kind} without a valid actor"));
+        match kind {
+            "tsumo" => kyoku.takes[actor()?].push(json!(tile(&event["pai"])?)),
+            "dahai" => {
+                let actor = actor()?;
+                let pai = match event["tsumogiri"].as_bool() {
+                    Some(true) => 60,
+                    _ => tile(&event["pai"])?,
+                };
+                // 立直宣言牌记为 `r` 加上打出的牌
+                let discard = if mem::take(&mut kyoku.reach[actor]) {
+                    json!(format!("r{pai}"))
+                } else {
+                    json!(pai)
+                };
+                kyoku.discards[actor].push(discard);
+            }
+            "reach" => kyoku.reach[actor()?] = true,
+            "chi" | "pon" | "daiminkan" => {
+                let actor = actor()?;
+                let target = event["target"]
+                    .as_u64()
+                    .map(|t| t as usize)
+                    .filter(|&t| t < 4 && t != actor)
+                    .with_context(|| format!("event #{i}: {kind} without a valid target"))?;
+                let called = tile(&event["pai"])?;
+                let consumed = tiles(&event["consumed"])?;
+                let (letter, count) = match kind {
+                    "chi" => ('c', 2),
+                    "pon" => ('p', 2),
+                    _ => ('m', 3),
+                };
+                ensure!(consumed.len() == count, "event #{i}: {kind} needs {count} consumed tiles");
+                // 鸣入的牌放在字母之后，位置表示来自哪一家：上家在最前，对家为第二张，下家在最后
+                let position = match (target + 4 - actor) % 4 {
+                    3 => 0,
+                    2 if letter != 'c' => 1,
+                    1 if letter != 'c' => count,
+                    _ => bail!("event #{i}: seat {actor} cannot {kind} from seat {target}"),
+                };
+                let (before, after) = consumed.split_at(position);
+                let [before, after] = [before, after].map(join_tiles);
+                kyoku.takes[actor].push(json!(format!("{before}{letter}{called}{after}")));
+                match letter {
+                    'p' => {
+                        let rest = format!("{called}{after}");
+                        kyoku.pons[actor].push((deaka(called), before, rest));
+                    }
+                    // 大明杠后不打牌，打牌处记为 0
+                    'm' => kyoku.discards[actor].push(json!(0)),
+                    _ => (),
+                }
+            }
+            "kakan" => {
+                let actor = actor()?;
+                let added = tile(&event["pai"])?;
+                let pos = kyoku.pons[actor]
+                    .iter()
+                    .position(|(pai, ..)| *pai == deaka(added))
+                    .with_context(|| format!("event #{i}: kakan without a matching pon"))?;
+                // 加上的牌插在碰时鸣入的牌之前
+                let (_, before, rest) = kyoku.pons[actor].remove(pos);
+                kyoku.discards[actor].push(json!(format!("{before}k{added}{rest}")));
+            }
+            "ankan" => {
+                let actor = actor()?;
+                let consumed = tiles(&event["consumed"])?;
+                let (last, rest) = consumed
+                    .split_last()
+                    .filter(|(_, rest)| rest.len() == 3)
+                    .with_context(|| format!("event #{i}: ankan needs 4 consumed tiles"))?;
+                kyoku.discards[actor].push(json!(format!("{}a{last}", join_tiles(rest))));
+            }
+            "dora" => kyoku.dora.push(tile(&event["dora_marker"])?),
+            "reach_accepted" => (),
+            "hora" => {
+                let actor = actor()?;
+                let target = event["target"].as_u64().map_or(actor, |t| t as usize);
+                // 一炮多响时每个和了都带有相同的里宝牌指示牌
+                if kyoku.ura.is_empty() {
+                    kyoku.ura = tiles(&event["ura_markers"])?;
+                }
+                if kyoku.result.is_empty() {
+                    kyoku.result.push(json!("和了"));
+                }
+                kyoku.result.push(deltas(event));
+                kyoku.result.push(json!([actor, target, actor]));
+            }
+            "ryukyoku" => kyoku.result = vec![json!("流局"), deltas(event)],
+            "end_kyoku" => {
+                let kyoku = current.take().expect("checked above");
+                if aka.is_none() && kyoku.has_aka() {
+                    aka = Some(true);
+                }
+                log.push(kyoku.into_json());
+            }
+            _ => bail!("event #{i}: {kind} events are not supported"),
+        }
+    }
+
+    let aka = aka.unwrap_or_default();
+    let disp = format!("般{}喰{}", if hanchan { "南" } else { "東" }, if aka { "赤" } else { "" });
+    let red = u8::from(aka);
+    Ok(json!({
+        "title": ["", ""],
+        "name": names,
+        "rule": { "disp": disp, "aka": red, "aka51": red, "aka52": red, "aka53": red },
+        "log": log,
+    }))
+}
+
+/// [`mjai_to_tenhou`] 中正在生成的一局
+#[derive(Default)]
+struct TenhouKyoku {
+    /// `[局序号, 本场, 供托]`
+    meta: json::Value,
+    scores: json::Value,
+    dora: Vec<u64>,
+    ura: Vec<u64>,
+    haipai: [Vec<u64>; 4],
+    takes: [Vec<json::Value>; 4],
+    discards: [Vec<json::Value>; 4],
+    /// 已宣言立直，下一张打牌为立直宣言牌
+    reach: [bool; 4],
+    /// 各家的碰：鸣入的牌（赤五记为普通的五），以及副露字符串中鸣入的牌之前与之后的部分
+    pons: [Vec<(u64, String, String)>; 4],
+    result: Vec<json::Value>,
+}
+
+impl TenhouKyoku {
+    /// 配牌、摸牌和宝牌指示牌中是否出现了赤五
+    fn has_aka(&self) -> bool {
+        let takes = self.takes.iter().flatten().filter_map(json::Value::as_u64);
+        self.haipai
+            .iter()
+            .flatten()
+            .chain(&self.dora)
+            .copied()
+            .chain(takes)
+            .any(|tile| tile >= 51)
+    }
+
+    /// 按天凤的顺序排列：局信息、点数、宝牌、里宝牌、各家的配牌/摸牌/打牌、结果
+    fn into_json(self) -> json::Value {
+        let mut kyoku = vec![self.meta, self.scores, json!(self.dora), json!(self.ura)];
+        let seats = self.haipai.into_iter().zip(self.takes).zip(self.discards);
+        for ((haipai, takes), discards) in seats {
+            kyoku.extend([json!(haipai), json!(takes), json!(discards)]);
+        }
+        kyoku.push(json!(self.result));
+        json::Value::Array(kyoku)
+    }
+}
+
+/// 将一张 mjai 格式的牌转换为天凤格式，与 [`tenhou_tile_to_mjai`] 相反。
+/// 不是 mjai 格式的牌（包括表示不明的牌的 `?`）返回 `None`
+fn mjai_tile_to_tenhou(tile: &json::Value) -> Option<u64> {
+    const HONORS: [&str; 7] = ["E", "S", "W", "N", "P", "F", "C"];
+
+    let tile = tile.as_str()?;
+    if let Some(n) = HONORS.iter().position(|&h| h == tile) {
+        return Some(41 + n as u64);
+    }
+    match tile {
+        "5mr" => Some(51),
+        "5pr" => Some(52),
+        "5sr" => Some(53),
+        _ => {
+            let mut chars = tile.chars();
+            let number = chars.next()?.to_digit(10).filter(|n| (1..=9).contains(n))?;
+            let suit = "mps".find(chars.next()?)? as u64;
+            chars.next().is_none().then_some((suit + 1) * 10 + u64::from(number))
+        }
+    }
+}
+
+/// 天凤格式的赤五换成普通的五
+const fn deaka(tile: u64) -> u64 {
+    match tile {
+        51..=53 => (tile - 50) * 10 + 5,
+        tile => tile,
+    }
+}
+
+/// 将天凤格式的牌依次写成两位数拼接的字符串，用于副露字符串
+fn join_tiles(tiles: &[u64]) -> String {
+    tiles.iter().map(u64::to_string).collect()
+}
+
+/// 检查每局中亮出的牌（配牌、摸牌、宝牌与里宝牌指示牌）没有超过一副牌中的张数：
+/// 每种牌 4 张，其中赤五各 1 张。天凤牌谱不记录没有摸到的牌山，因此无法检查是否缺牌。
+///
+/// 同时重放整场比赛（见 [`final_hands`]），打出或鸣牌用的牌不在手牌中时返回错误。
+pub fn check_wall(events: &[Event]) -> Result<Vec<Mismatch>> {
+    final_hands(events)?;
+
+    let mut mismatches = vec![];
+    let mut kyoku_index = None;
+    // 键为牌名，赤五同时计入普通的五
+    let mut counts = BTreeMap::<String, u32>::new();
+    let mut ura_seen = false;
+    for event in event_values(events)? {
+        let mut add = |tiles: &[&json::Value]| {
+            for tile in tiles.iter().filter_map(|t| t.as_str()).filter(|&t| t != "?") {
+                *counts.entry(tile.to_owned()).or_default() += 1;
+                if let Some(normal) = tile.strip_suffix('r') {
+                    *counts.entry(normal.to_owned()).or_default() += 1;
+                }
+            }
+        };
+
+        match event["type"].as_str() {
+            Some("start_kyoku") => {
+                kyoku_index = Some(kyoku_index.map_or(0, |i| i + 1));
+                ura_seen = false;
+                let tehais = event["tehais"].as_array().map(Vec::as_slice).unwrap_or_default();
+                let tiles = tehais.iter().filter_map(json::Value::as_array).flatten();
+                add(&tiles.chain(iter::once(&event["dora_marker"])).collect::<Vec<_>>());
+            }
+            Some("tsumo") => add(&[&event["pai"]]),
+            Some("dora") => add(&[&event["dora_marker"]]),
+            // 一炮多响时每个和了都带有相同的里宝牌指示牌
+            Some("hora") if !ura_seen => {
+                ura_seen = true;
+                let ura = event["ura_markers"].as_array().map(Vec::as_slice).unwrap_or_default();
+                add(&ura.iter().collect::<Vec<_>>());
+            }
+            Some("end_kyoku") => {
+                for (tile, &count) in &counts {
+                    let limit = if tile.ends_with('r') { 1 } else { 4 };
+                    if count > limit {
+                        mismatches.push(Mismatch {
+                            kyoku_index,
+                            field: "tile count",
+                            expected: format!("at most {limit} {tile}"),
+                            actual: count.to_string(),
+                        });
+                    }
+                }
+                counts.clear();
+            }
+            _ => (),
+        }
+    }
+    Ok(mismatches)
+}
+
+/// 将 mjai 事件序列化为 JSON，便于按 mjai 协议中的字段名检查事件内容
+fn event_values(events: &[Event]) -> Result<Vec<json::Value>> {
+    events
+        .iter()
+        .map(json::to_value)
+        .collect::<Result<_, _>>()
+        .context("failed to serialize mjai events")
+}
+
+/// 批量转换中各类处理结果的文件数
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConvertStats {
+    pub processed: usize,
+    /// 被 `--game-length`、`--room` 等条件过滤掉的文件数
+    pub filtered: usize,
+    /// 因输出已是最新而跳过的文件数
+    pub skipped: usize,
+    pub duplicates: usize,
+    /// 重复文件的总字节数
+    pub duplicate_bytes: usize,
+    pub errors: usize,
+    /// 转换失败的文件及其错误信息，按失败的先后排列。读取目录、文件列表等本身的错误只计入
+    /// `errors`
+    pub failures: Vec<ConvertFailure>,
+    /// 指定 `--fail-fast` 时第一个转换失败的文件
+    pub first_failure: Option<PathBuf>,
+}
+
+/// 一个转换失败的文件
+#[derive(Debug, Clone, Serialize)]
+pub struct ConvertFailure {
+    pub path: PathBuf,
+    /// 包含各层原因的错误信息
+    pub message: String,
+}
+
+impl ConvertStats {
+    /// 所有文件都处理成功（包括被过滤或跳过的）时为 `true`
+    pub const fn is_success(&self) -> bool {
+        self.errors == 0 && self.first_failure.is_none()
+    }
+}
+
+/// 单个座位的和了、放铳与立直次数
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SeatStats {
+    pub agari: u32,
+    pub tsumo: u32,
+    pub ron: u32,
+    /// 放铳次数
+    pub houjuu: u32,
+    /// 立直宣言次数（包括宣言牌被荣和的情况）
+    pub riichi: u32,
+}
+
+/// 一批牌谱按座位汇总的统计
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchStats {
+    pub games: u32,
+    pub kyoku: u32,
+    pub seats: [SeatStats; 4],
+}
+
+impl BatchStats {
+    /// 将一场比赛的 mjai 事件计入统计
+    pub fn add_game(&mut self, events: &[Event]) -> Result<()> {
+        self.games += 1;
+        for (i, event) in event_values(events)?.iter().enumerate() {
+            let actor = event_seat(i, event, "actor")?;
+            match (event["type"].as_str(), actor) {
+                (Some("start_kyoku"), _) => self.kyoku += 1,
+                (Some("reach"), Some(actor)) => self.seats[actor].riichi += 1,
+                (Some("hora"), Some(actor)) => {
+                    let target = event_seat(i, event, "target")?.unwrap_or(actor);
+                    let seat = &mut self.seats[actor];
+                    seat.agari += 1;
+                    if target == actor {
+                        seat.tsumo += 1;
+                    } else {
+                        seat.ron += 1;
+                        self.seats[target].houjuu += 1;
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 单个玩家的打法倾向统计。巡目按该玩家在本局中的摸牌次数计。
+#[derive(Debug, Clone, Default)]
+pub struct PlayerStats {
+    pub games: u32,
+    pub kyoku: u32,
+    pub riichi: u32,
+    /// 所有立直宣言时的巡目之和
+    pub riichi_turns: u32,
+    /// 吃、碰、大明杠的次数
+    pub calls: u32,
+    /// 有过吃、碰、大明杠的局数
+    pub called_kyoku: u32,
+    /// 自己未立直时遭遇他家立直的局数
+    pub faced_riichi: u32,
+    /// 遭遇他家立直后既没有立直、鸣牌、和了、放铳，流局时也没有收到点数的局数
+    pub folded: u32,
+    /// 和了巡目 -> 次数
+    pub win_turns: BTreeMap<u32, u32>,
+}
+
+impl PlayerStats {
+    /// 平均立直巡目，没有立直过时为 `None`
+    pub fn average_riichi_turn(&self) -> Option<f64> {
+        (self.riichi > 0).then(|| f64::from(self.riichi_turns) / f64::from(self.riichi))
+    }
+
+    /// 遭遇他家立直后弃和的比例
+    pub fn fold_rate(&self) -> Option<f64> {
+        (self.faced_riichi > 0).then(|| f64::from(self.folded) / f64::from(self.faced_riichi))
+    }
+
+    /// 有鸣牌的局数占所有局数的比例
+    pub fn call_rate(&self) -> Option<f64> {
+        (self.kyoku > 0).then(|| f64::from(self.called_kyoku) / f64::from(self.kyoku))
+    }
+}
+
+impl Serialize for PlayerStats {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        json!({
+            "games": self.games,
+            "kyoku": self.kyoku,
+            "riichi": self.riichi,
+            "average_riichi_turn": self.average_riichi_turn(),
+            "faced_riichi": self.faced_riichi,
+            "folded": self.folded,
+            "fold_rate": self.fold_rate(),
+            "calls": self.calls,
+            "called_kyoku": self.called_kyoku,
+            "call_rate": self.call_rate(),
+            "win_turns": self.win_turns,
+        })
+        .serialize(serializer)
+    }
+}
+
+/// 一批牌谱按玩家名汇总的打法倾向统计，只依据 mjai 事件计算
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct PlayerTendencies {
+    pub players: BTreeMap<String, PlayerStats>,
+}
+
+impl PlayerTendencies {
+    /// 将一场比赛的 mjai 事件计入统计
+    pub fn add_game(&mut self, events: &[Event]) -> Result<()> {
+        let mut names: [String; 4] = Default::default();
+        let mut game: [PlayerStats; 4] = Default::default();
+        // 本局中各座位的摸牌次数、是否已立直、是否遭遇他家立直、遭遇后是否仍在进攻、是否鸣牌
+        let mut turns = [0; 4];
+        let mut in_riichi = [false; 4];
+        let mut faced = [false; 4];
+        let mut pushed = [false; 4];
+        let mut called = [false; 4];
+
+        for (i, event) in event_values(events)?.iter().enumerate() {
+            let actor = event_seat(i, event, "actor")?;
+            match (event["type"].as_str(), actor) {
+                (Some("start_game"), _) => {
+                    let players = event["names"].as_array().map(Vec::as_slice);
+                    for (name, value) in names.iter_mut().zip(players.unwrap_or_default()) {
+                        value.as_str().unwrap_or_default().clone_into(name);
+                    }
+                }
+                (Some("start_kyoku"), _) => {
+                    turns = [0; 4];
+                    in_riichi = [false; 4];
+                    faced = [false; 4];
+                    pushed = [false; 4];
+                    called = [false; 4];
+                    game.iter_mut().for_each(|p| p.kyoku += 1);
+                }
+                (Some("tsumo"), Some(actor)) => turns[actor] += 1,
+                (Some("chi" | "pon" | "daiminkan"), Some(actor)) => {
+                    game[actor].calls += 1;
+                    called[actor] = true;
+                    pushed[actor] = true;
+                }
+                (Some("reach"), Some(actor)) => {
+                    game[actor].riichi += 1;
+                    game[actor].riichi_turns += turns[actor];
+                    pushed[actor] = true;
+                }
+                (Some("reach_accepted"), Some(actor)) => {
+                    in_riichi[actor] = true;
+                    for seat in (0..4).filter(|&s| s != actor && !in_riichi[s] && !faced[s]) {
+                        faced[seat] = true;
+                        pushed[seat] = false;
+                    }
+                }
+                (Some("hora"), Some(actor)) => {
+                    let target = event_seat(i, event, "target")?.unwrap_or(actor);
+                    *game[actor].win_turns.entry(turns[actor]).or_default() += 1;
+                    pushed[actor] = true;
+                    pushed[target] = true;
+                }
+                (Some("ryukyoku"), _) => {
+                    let deltas = event["deltas"].as_array().map(Vec::as_slice);
+                    for (seat, delta) in deltas.unwrap_or_default().iter().enumerate().take(4) {
+                        if delta.as_i64().unwrap_or_default() > 0 {
+                            pushed[seat] = true;
+                        }
+                    }
+                }
+                (Some("end_kyoku"), _) => {
+                    for (seat, player) in game.iter_mut().enumerate() {
+                        player.called_kyoku += u32::from(called[seat]);
+                        player.faced_riichi += u32::from(faced[seat]);
+                        player.folded += u32::from(faced[seat] && !pushed[seat]);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for (name, player) in names.into_iter().zip(game) {
+            let total = self.players.entry(name).or_default();
+            total.games += 1;
+            total.kyoku += player.kyoku;
+            total.riichi += player.riichi;
+            total.riichi_turns += player.riichi_turns;
+            total.calls += player.calls;
+            total.called_kyoku += player.called_kyoku;
+            total.faced_riichi += player.faced_riichi;
+            total.folded += player.folded;
+            for (turn, count) in player.win_turns {
+                *total.win_turns.entry(turn).or_default() += count;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 单个役在一批牌谱的和了中出现的次数与番数
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YakuCount {
+    pub count: u32,
+    /// 所有出现中番数的总和，役满不计
+    pub han: u32,
+    pub yakuman: bool,
+}
+
+impl YakuCount {
+    /// 平均每次带来的番数，役满为 `None`
+    pub fn average_han(&self) -> Option<f64> {
+        (!self.yakuman && self.count > 0).then(|| f64::from(self.han) / f64::from(self.count))
+    }
+}
+
+impl Serialize for YakuCount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        json!({
+            "count": self.count,
+            "yakuman": self.yakuman,
+            "average_han": self.average_han(),
+        })
+        .serialize(serializer)
+    }
+}
+
+/// 一批牌谱和了中各役（包括宝牌和役满）的出现次数，取自天凤牌谱中记录的役
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct YakuStats {
+    pub agari: u32,
+    /// 役名（如 `立直`、`役牌 中`）-> 出现次数
+    pub yaku: BTreeMap<String, YakuCount>,
+}
+
+impl YakuStats {
+    /// 将一场比赛的天凤牌谱 `raw` 中所有和了的役计入统计
+    pub fn add_game(&mut self, raw: &json::Value) {
+        let kyokus = raw["log"].as_array().map(Vec::as_slice).unwrap_or_default();
+        for kyoku in kyokus {
+            // 结果数组形如 ["和了", 点数变动, 和了信息, 点数变动, 和了信息, ...]，
+            // 和了信息为 [和了者, 放铳者, 包牌者, 点数, 役...]
+            let result = kyoku.as_array().and_then(|k| k.last()).and_then(json::Value::as_array);
+            let Some(result) = result.filter(|r| r.first().is_some_and(|kind| kind == "和了")) else {
+                continue;
+            };
+            for agari in result.iter().skip(2).step_by(2).filter_map(json::Value::as_array) {
+                self.agari += 1;
+                for yaku in agari.iter().skip(4).filter_map(json::Value::as_str) {
+                    let (name, han) = yaku.rsplit_once('(').unwrap_or((yaku, ""));
+                    let han = han.trim_end_matches(')');
+                    // 立直和了时即使没有里宝牌也会记录 `裏ドラ(0飜)`，这样的宝牌不算出现
+                    if han == "0飜" {
+                        continue;
+                    }
+                    let count = self.yaku.entry(name.to_owned()).or_default();
+                    count.count += 1;
+                    match han {
+                        "役満" => count.yakuman = true,
+                        han => {
+                            let han = han.trim_end_matches('飜').parse::<u32>();
+                            count.han += han.unwrap_or_default();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 将天凤格式的牌（11~19 万子、21~29 筒子、31~39 索子、41~47 字牌、
+/// 51~53 红五）数组转换为 mjai 格式的牌
+fn tenhou_tiles_to_mjai(tiles: &json::Value) -> json::Value {
+    let tiles = tiles.as_array().map(Vec::as_slice).unwrap_or_default();
+    tiles
+        .iter()
+        .map(|tile| json::Value::String(tenhou_tile_to_mjai(tile.as_u64().unwrap_or_default())))
+        .collect()
+}
+
+/// 将一张天凤格式的牌转换为 mjai 格式，见 [`tenhou_tiles_to_mjai`]
+fn tenhou_tile_to_mjai(tile: u64) -> String {
+    const HONORS: [&str; 7] = ["E", "S", "W", "N", "P", "F", "C"];
+
+    match tile {
+        t @ 11..=19 => format!("{}m", t - 10),
+        t @ 21..=29 => format!("{}p", t - 20),
+        t @ 31..=39 => format!("{}s", t - 30),
+        t @ 41..=47 => HONORS[(t - 41) as usize].to_owned(),
+        51 => "5mr".to_owned(),
+        52 => "5pr".to_owned(),
+        53 => "5sr".to_owned(),
+        t => format!("?{t}"),
+    }
+}
+
+/// 把手牌解析为 mjai 格式的牌，手牌的张数和枚数见 [`hand_counts`]。
+///
+/// 接受 `123m456p789s11z22z` 这样的简写（见 [`parse_compact_hand`]），以及 mjai 牌谱中的
+/// `["1m","2m","3m"]` 这样的 JSON 数组，以 `[` 开头时按后者解析。两种写法不能混用。
+pub fn parse_hand(notation: &str) -> Result<Vec<String>> {
+    let tiles = if notation.trim_start().starts_with('[') {
+        parse_mjai_hand(notation)?
+    } else {
+        parse_compact_hand(notation)?
+    };
+    hand_counts(&tiles)?;
+    Ok(tiles)
+}
+
+/// 解析 `["1m","2m","3m"]` 形式的手牌，错误中给出出错的牌是第几个（从 1 开始）
+fn parse_mjai_hand(notation: &str) -> Result<Vec<String>> {
+    let tokens: Vec<json::Value> = json::from_str(notation)
+        .context("the hand starts with `[` but is not a JSON array of mjai tiles")?;
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let tile = token.as_str().filter(|tile| tile_index(tile).is_some());
+            let tile = tile.with_context(|| {
+                // 如 `"123m"`，简写混在了数组中
+                let compact = token.as_str().is_some_and(|t| t.ends_with(['m', 'p', 's', 'z']));
+                let hint = if compact {
+                    ", compact notation cannot be used inside [...]"
+                } else {
+                    ""
+                };
+                format!("token #{} {token} is not an mjai tile{hint}", i + 1)
+            })?;
+            Ok(tile.to_owned())
+        })
+        .collect()
+}
+
+/// 解析 `123m456p789s11z22z` 这样的简写。
+///
+/// 数字后跟花色：`m`、`p`、`s` 为万子、筒子、索子，其中 `0` 为赤五；`z` 为字牌，`1z`~`7z`
+/// 依次为东南西北白发中。空白会被忽略。写法不合法时错误中给出出错的位置（从 1 开始的字符序号）。
+fn parse_compact_hand(notation: &str) -> Result<Vec<String>> {
+    let mut tiles = vec![];
+    // 还没有遇到花色的数字及其位置
+    let mut numbers: Vec<(usize, u64)> = vec![];
+    for (i, c) in notation.chars().enumerate() {
+        let pos = i + 1;
+        match c {
+            '0'..='9' => numbers.push((pos, u64::from(c.to_digit(10).unwrap_or_default()))),
+            'm' | 'p' | 's' | 'z' => {
+                ensure!(!numbers.is_empty(),
+                        "`{c}` at position {pos} has no tile numbers before it");
+                let base = match c {
+                    'm' => 10,
+                    'p' => 20,
+                    's' => 30,
+                    _ => 40,
+                };
+                for (pos, n) in numbers.drain(..) {
+                    let tile = match (c, n) {
+                        ('z', 1..=7) => base + n,
+                        ('z', _) => {
+                            bail!("`{n}z` at position {pos} is not a tile, honors are 1z to 7z")
+                        }
+                        // 天凤的赤五为 51~53
+                        (_, 0) => 50 + base / 10,
+                        _ => base + n,
+                    };
+                    tiles.push(tenhou_tile_to_mjai(tile));
+                }
+            }
+            c if c.is_whitespace() => (),
+            // 如 `123m"4p"` 或 `123mE`，混入了 mjai 的写法
+            '"' | ',' | '[' | ']' | 'A'..='Z' => {
+                bail!("unexpected `{c}` at position {pos}, mjai tiles must be given as a whole \
+                       JSON array such as [\"1m\",\"E\"]")
+            }
+            c => bail!("unexpected `{c}` at position {pos}, expected a digit or one of m, p, s, z"),
+        }
+    }
+    if let Some(&(pos, _)) = numbers.first() {
+        bail!("the tile numbers from position {pos} have no suit (m, p, s or z) after them");
+    }
+    Ok(tiles)
+}
+
+/// 手牌中每种牌的枚数，下标见 [`tile_index`]。
+///
+/// 手牌应为 3n+1 或 3n+2 张且不超过 14 张，每种牌不超过 4 张、每种赤五不超过 1 张，否则返回错误。
+fn hand_counts<S: AsRef<str>>(tiles: &[S]) -> Result<[u8; 34]> {
+    ensure!(tiles.len() <= 14, "the hand has {} tiles, at most 14 are allowed", tiles.len());
+    ensure!(tiles.len() % 3 != 0,
+            "the hand has {} tiles, a hand has 3n+1 or 3n+2 tiles",
+            tiles.len());
+    let mut counts = [0; 34];
+    let mut reds = [false; 3];
+    for tile in tiles {
+        let tile = tile.as_ref();
+        let index = tile_index(tile).with_context(|| format!("{tile:?} is not a tile"))?;
+        counts[index] += 1;
+        ensure!(counts[index] <= 4, "the hand has more than 4 {}", index_tile(index));
+        if tile.ends_with('r') {
+            ensure!(!mem::replace(&mut reds[index / 9], true), "the hand has more than one {tile}");
+        }
+    }
+    Ok(counts)
+}
+
+/// mjai 格式的牌的序号：万子 0~8、筒子 9~17、索子 18~26、字牌 27~33，赤五与普通的五相同。
+/// 不是牌时返回 `None`。
+fn tile_index(tile: &str) -> Option<usize> {
+    let (suit, number, red) = tile_sort_key(tile);
+    let index = match suit {
+        0..=2 if (1..=9).contains(&number) => suit * 9 + number - 1,
+        3 => 27 + number,
+        _ => return None,
+    };
+    // tile_sort_key 不检查多余的字符
+    let plain = index_tile(index);
+    let valid = if red { number == 5 && tile == format!("{plain}r") } else { tile == plain };
+    valid.then_some(index)
+}
+
+/// [`tile_index`] 的逆运算，得到的五都不是赤五
+fn index_tile(index: usize) -> String {
+    let tile = match index {
+        0..=26 => (index / 9 + 1) * 10 + index % 9 + 1,
+        _ => 41 + index - 27,
+    };
+    tenhou_tile_to_mjai(tile as u64)
+}
+
+/// 手牌的向听数：和了形为 -1，听牌为 0。
+///
+/// 手牌为 3n+1 或 3n+2 张（见 [`hand_counts`]），不足 13 张时缺少的部分视为已经副露的面子。
+/// 取一般形、七对子和国士无双中最小的，七对子和国士无双只在没有副露时计算。
+pub fn shanten<S: AsRef<str>>(tiles: &[S]) -> Result<i8> {
+    Ok(counts_shanten(&hand_counts(tiles)?))
+}
+
+/// 3n+1 张手牌的向听数，以及摸到后向听数减少的牌（进张）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ukeire {
+    pub shanten: i8,
+    /// 进张的种类，按 [`tile_sort_key`] 排序，不区分赤五
+    pub tiles: Vec<String>,
+    /// 进张的总枚数。每种牌按 4 张减去手牌中的枚数计，不考虑其他已经见到的牌
+    pub count: u32,
+}
+
+/// 3n+1 张手牌的 [`Ukeire`]
+pub fn ukeire<S: AsRef<str>>(tiles: &[S]) -> Result<Ukeire> {
+    let mut counts = hand_counts(tiles)?;
+    ensure!(tiles.len() % 3 == 1,
+            "the hand has {} tiles, ukeire needs 3n+1 tiles; use discard_ukeire",
+            tiles.len());
+    Ok(counts_ukeire(&mut counts))
+}
+
+/// 3n+2 张手牌每种打法打出后的 [`Ukeire`]，按向听数从小到大、进张枚数从多到少排列。
+/// 打出的牌不区分赤五。
+pub fn discard_ukeire<S: AsRef<str>>(tiles: &[S]) -> Result<Vec<(String, Ukeire)>> {
+    let mut counts = hand_counts(tiles)?;
+    ensure!(tiles.len() % 3 == 2,
+            "the hand has {} tiles, discard_ukeire needs 3n+2 tiles; use ukeire",
+            tiles.len());
+    let mut options = vec![];
+    for index in 0..34 {
+        if counts[index] == 0 {
+            continue;
+        }
+        counts[index] -= 1;
+        options.push((index_tile(index), counts_ukeire(&mut counts)));
+        counts[index] += 1;
+    }
+    options.sort_by_key(|(_, ukeire)| (ukeire.shanten, Reverse(ukeire.count)));
+    Ok(options)
+}
+
+/// [`ukeire`]，`counts` 用完后恢复原样
+fn counts_ukeire(counts: &mut [u8; 34]) -> Ukeire {
+    let shanten = counts_shanten(counts);
+    let mut tiles = vec![];
+    let mut count = 0;
+    for index in 0..34 {
+        if counts[index] == 4 {
+            continue;
+        }
+        let remaining = 4 - counts[index];
+        counts[index] += 1;
+        if counts_shanten(counts) < shanten {
+            tiles.push(index_tile(index));
+            count += u32::from(remaining);
+        }
+        counts[index] -= 1;
+    }
+    Ukeire { shanten, tiles, count }
+}
+
+/// [`shanten`]
+fn counts_shanten(counts: &[u8; 34]) -> i8 {
+    const TERMINALS: [usize; 13] = [0, 8, 9, 17, 18, 26, 27, 28, 29, 30, 31, 32, 33];
+
+    let tile_count: u8 = counts.iter().sum();
+    let melds = (14 - tile_count) / 3;
+    let mut shanten = standard_shanten(counts, melds);
+    if melds == 0 {
+        let kinds = counts.iter().filter(|&&c| c > 0).count() as i8;
+        let pairs = counts.iter().filter(|&&c| c >= 2).count() as i8;
+        shanten = shanten.min(6 - pairs + (7 - kinds).max(0));
+
+        let kinds = TERMINALS.iter().filter(|&&i| counts[i] > 0).count() as i8;
+        let pair = TERMINALS.iter().any(|&i| counts[i] >= 2);
+        shanten = shanten.min(13 - kinds - i8::from(pair));
+    }
+    shanten
+}
+
+/// 一般形（4 面子 1 雀头）的向听数，`melds` 为已经副露的面子数。
+///
+/// 向听数为 8 - 2 × 面子数 - 搭子数 - 有无雀头，面子数与搭子数之和不超过 4。
+/// 各花色分别求出能取出的面子数与搭子数，再合并起来。
+fn standard_shanten(counts: &[u8; 34], melds: u8) -> i8 {
+    // 已合并的花色中能取出的 (面子数, 搭子数)，分为还没有取出雀头和已经取出雀头
+    let mut without_pair = vec![(melds, 0)];
+    let mut with_pair = vec![];
+    for (suit, group) in counts.chunks(9).enumerate() {
+        let sequences = suit < 3;
+        let mut group = group.to_vec();
+        let plain = block_options(&mut group, sequences);
+        let mut paired = vec![];
+        for i in 0..group.len() {
+            if group[i] >= 2 {
+                group[i] -= 2;
+                paired.extend(block_options(&mut group, sequences));
+                group[i] += 2;
+            }
+        }
+        with_pair = pareto(combine(&with_pair, &plain).chain(combine(&without_pair, &paired)));
+        without_pair = pareto(combine(&without_pair, &plain));
+    }
+
+    let shanten = |&(mentsu, taatsu): &(u8, u8), pair: i8| {
+        let mentsu = mentsu.min(4);
+        8 - 2 * mentsu as i8 - taatsu.min(4 - mentsu) as i8 - pair
+    };
+    let without_pair = without_pair.iter().map(|blocks| shanten(blocks, 0));
+    let with_pair = with_pair.iter().map(|blocks| shanten(blocks, 1));
+    without_pair.chain(with_pair).min().unwrap_or(8)
+}
+
+/// 两组 (面子数, 搭子数) 两两相加
+fn combine<'a>(a: &'a [(u8, u8)], b: &'a [(u8, u8)]) -> impl Iterator<Item = (u8, u8)> + 'a {
+    a.iter().flat_map(move |&(m1, t1)| b.iter().map(move |&(m2, t2)| (m1 + m2, t1 + t2)))
+}
+
+/// 去掉面子数和搭子数都不多于另一项的 (面子数, 搭子数)
+fn pareto(options: impl Iterator<Item = (u8, u8)>) -> Vec<(u8, u8)> {
+    let mut options: Vec<_> = options.collect();
+    options.sort_unstable_by(|a, b| b.cmp(a));
+    options.dedup();
+    let mut kept: Vec<(u8, u8)> = vec![];
+    for option in options {
+        if kept.iter().all(|&(_, taatsu)| taatsu < option.1) {
+            kept.push(option);
+        }
+    }
+    kept
+}
+
+/// 一种花色（`sequences` 为 `false` 时为字牌）中能同时取出的 (面子数, 搭子数)
+fn block_options(group: &mut [u8], sequences: bool) -> Vec<(u8, u8)> {
+    let mut options = vec![];
+    search_blocks(group, 0, sequences, (0, 0), &mut options);
+    pareto(options.into_iter())
+}
+
+fn search_blocks(
+    group: &mut [u8],
+    from: usize,
+    sequences: bool,
+    (mentsu, taatsu): (u8, u8),
+    options: &mut Vec<(u8, u8)>,
+) {
+    let Some(i) = (from..group.len()).find(|&i| group[i] > 0) else {
+        options.push((mentsu, taatsu));
+        return;
+    };
+    // 依次尝试以 i 开头的刻子、顺子、对子、两面或边张、嵌张，最后把它当作孤张
+    let shapes: [(&[usize], u8, u8); 5] = [
+        (&[0, 0, 0], 1, 0),
+        (&[0, 1, 2], 1, 0),
+        (&[0, 0], 0, 1),
+        (&[0, 1], 0, 1),
+        (&[0, 2], 0, 1),
+    ];
+    for (offsets, m, t) in shapes {
+        let span = offsets.last().copied().unwrap_or_default();
+        if span > 0 && (!sequences || i + span >= group.len()) {
+            continue;
+        }
+        let mut removed = 0;
+        for &offset in offsets {
+            if group[i + offset] == 0 {
+                break;
+            }
+            group[i + offset] -= 1;
+            removed += 1;
+        }
+        if removed == offsets.len() {
+            search_blocks(group, i, sequences, (mentsu + m, taatsu + t), options);
+        }
+        for &offset in &offsets[..removed] {
+            group[i + offset] += 1;
+        }
+    }
+    group[i] -= 1;
+    search_blocks(group, i, sequences, (mentsu, taatsu), options);
+    group[i] += 1;
+}
+
+/// 64 位 FNV-1a 哈希。
+///
+/// 与 `DefaultHasher` 不同，结果不随 Rust 版本或平台变化，可以写进缓存文件名等需要持久化的地方。
+/// 它不是密码学哈希，只用于缓存键、`--dedup` 和 `--shard` 等，不能用来隐藏内容。
+#[derive(Clone, Copy, Debug)]
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for ContentHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// 本程序输出的 mjai 事件的 JSON Schema（draft 2020-12）。
+///
+/// 手写，与 convlog 的 [`Event`] 序列化后的形式对应。转换器不支持三麻，
+/// 因此不包含 `nukidora` 等三麻专用的事件。
+pub fn mjai_event_schema() -> json::Value {
+    let actor = json!({ "$ref": "#/$defs/actor" });
+    let tile = json!({ "$ref": "#/$defs/tile" });
+    let tiles = |n: usize| json!({ "type": "array", "items": tile, "minItems": n, "maxItems": n });
+    let deltas = json!({ "$ref": "#/$defs/scores" });
+    let event = |name: &str, properties: json::Value, required: &[&str]| {
+        let mut properties = properties;
+        properties["type"] = json!({ "const": name });
+        let required: Vec<_> = iter::once("type").chain(required.iter().copied()).collect();
+        json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    };
+
+    let events = [
+        event(
+            "start_game",
+            json!({
+                "names": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "minItems": 4,
+                    "maxItems": 4,
+                },
+            }),
+            &["names"],
+        ),
+        event(
+            "start_kyoku",
+            json!({
+                "bakaze": { "enum": ["E", "S", "W", "N"] },
+                "dora_marker": tile,
+                "kyoku": { "type": "integer", "minimum": 1, "maximum": 4 },
+                "honba": { "type": "integer", "minimum": 0 },
+                "kyotaku": { "type": "integer", "minimum": 0 },
+                "oya": actor,
+                "scores": { "$ref": "#/$defs/scores" },
+                "tehais": { "type": "array", "items": tiles(13), "minItems": 4, "maxItems": 4 },
+            }),
+            &["bakaze", "dora_marker", "kyoku", "honba", "kyotaku", "oya", "scores", "tehais"],
+        ),
+        event("tsumo", json!({ "actor": actor, "pai": tile }), &["actor", "pai"]),
+        event(
+            "dahai",
+            json!({ "actor": actor, "pai": tile, "tsumogiri": { "type": "boolean" } }),
+            &["actor", "pai", "tsumogiri"],
+        ),
+        event(
+            "chi",
+            json!({ "actor": actor, "target": actor, "pai": tile, "consumed": tiles(2) }),
+            &["actor", "target", "pai", "consumed"],
+        ),
+        event(
+            "pon",
+            json!({ "actor": actor, "target": actor, "pai": tile, "consumed": tiles(2) }),
+            &["actor", "target", "pai", "consumed"],
+        ),
+        event(
+            "daiminkan",
+            json!({ "actor": actor, "target": actor, "pai": tile, "consumed": tiles(3) }),
+            &["actor", "target", "pai", "consumed"],
+        ),
+        event(
+            "kakan",
+            json!({ "actor": actor, "pai": tile, "consumed": tiles(3) }),
+            &["actor", "pai", "consumed"],
+        ),
+        event("ankan", json!({ "actor": actor, "consumed": tiles(4) }), &["actor", "consumed"]),
+        event("dora", json!({ "dora_marker": tile }), &["dora_marker"]),
+        event("reach", json!({ "actor": actor }), &["actor"]),
+        event("reach_accepted", json!({ "actor": actor }), &["actor"]),
+        event(
+            "hora",
+            json!({
+                "actor": actor,
+                "target": actor,
+                "deltas": deltas,
+                "ura_markers": { "type": "array", "items": tile },
+            }),
+            &["actor", "target"],
+        ),
+        event("ryukyoku", json!({ "deltas": deltas }), &[]),
+        event("end_kyoku", json!({}), &[]),
+        event("end_game", json!({}), &[]),
+    ];
+
+    let mut tile_names: Vec<_> = ["m", "p", "s"]
+        .iter()
+        .flat_map(|suit| (1..=9).map(move |n| format!("{n}{suit}")))
+        .collect();
+    let others = ["E", "S", "W", "N", "P", "F", "C", "5mr", "5pr", "5sr", "?"];
+    tile_names.extend(others.map(String::from));
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "mjai event",
+        "description": "A single event of an mjai log, one per line in NDJSON output.",
+        "oneOf": events,
+        "$defs": {
+            "actor": { "type": "integer", "minimum": 0, "maximum": 3 },
+            "tile": {
+                "description": "An mjai tile; `?` is an unknown (hidden) tile.",
+                "enum": tile_names,
+            },
+            "scores": {
+                "type": "array",
+                "items": { "type": "integer" },
+                "minItems": 4,
+                "maxItems": 4,
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names_of(raw: &json::Value) -> Vec<&str> {
+        raw["name"].as_array().unwrap().iter().map(|name| name.as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn normalize_names_replaces_empty_and_null_names() {
+        let mut raw = json!({ "name": ["", null, "A", ""] });
+        let warnings = normalize_names(&mut raw);
+        assert!(warnings.is_empty());
+        assert_eq!(names_of(&raw), ["player0", "player1", "A", "player3"]);
+    }
+
+    #[test]
+    fn normalize_names_fills_missing_names() {
+        let mut raw = json!({ "name": ["A"] });
+        assert!(normalize_names(&mut raw).is_empty());
+        assert_eq!(names_of(&raw), ["A", "player1", "player2", "player3"]);
+
+        let mut raw = json!({});
+        assert!(normalize_names(&mut raw).is_empty());
+        assert_eq!(names_of(&raw), ["player0", "player1", "player2", "player3"]);
+    }
+
+    #[test]
+    fn normalize_names_decodes_percent_encoding() {
+        let mut raw = json!({ "name": ["%E3%81%82", "a%20b", "100%", "%zz"] });
+        assert!(normalize_names(&mut raw).is_empty());
+        // 不是合法的 URL 编码时保留原样
+        assert_eq!(names_of(&raw), ["あ", "a b", "100%", "%zz"]);
+    }
+
+    #[test]
+    fn normalize_names_warns_on_invalid_encoding() {
+        // 解码结果不是合法的 UTF-8
+        let mut raw = json!({ "name": ["%E3%81", "B", 1, "D"] });
+        let warnings = normalize_names(&mut raw);
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(warnings[0].contains("seat 0"));
+        assert!(warnings[1].contains("seat 2"));
+        assert_eq!(names_of(&raw), ["player0", "B", "player2", "D"]);
+    }
+
+    /// 将第一个 `tsumo` 的 `actor` 改为不存在的座位 4
+    fn with_invalid_actor(log: &str) -> Vec<Event> {
+        let mut values = event_values(&convert_log(log).unwrap()).unwrap();
+        let tsumo = values.iter_mut().find(|event| event["type"] == "tsumo").unwrap();
+        tsumo["actor"] = json!(4);
+        values.into_iter().map(|event| json::from_value(event).unwrap()).collect()
+    }
+
+    #[test]
+    fn final_hands_rejects_invalid_actor() {
+        let events = with_invalid_actor(include_str!("fixtures/dealer_tsumo.json"));
+        let err = final_hands(&events).unwrap_err().to_string();
+        assert!(err.contains("tsumo with invalid actor 4"), "{err}");
+    }
+
+    #[test]
+    fn stats_reject_invalid_actor() {
+        let events = with_invalid_actor(include_str!("fixtures/chiitoitsu_ron.json"));
+        let err = BatchStats::default().add_game(&events).unwrap_err().to_string();
+        assert!(err.contains("tsumo with invalid actor 4"), "{err}");
+        let err = PlayerTendencies::default().add_game(&events).unwrap_err().to_string();
+        assert!(err.contains("tsumo with invalid actor 4"), "{err}");
+    }
+
+    #[test]
+    fn mjai_to_tenhou_round_trips_fixtures() {
+        for log in [
+            include_str!("fixtures/dealer_tsumo.json"),
+            include_str!("fixtures/chiitoitsu_ron.json"),
+        ] {
+            let raw = parse_tenhou_json(log).unwrap();
+            let events = convert_log(log).unwrap();
+            let converted = mjai_to_tenhou(&events).unwrap();
+
+            assert!(verify_events(&converted, &events).unwrap().is_empty());
+            assert_eq!(converted["name"], raw["name"]);
+            // 结果之前的部分与原始牌谱相同，和了信息只保留和了者、放铳者和包牌者
+            let (kyoku, raw_kyoku) = (&converted["log"][0], &raw["log"][0]);
+            for k in 0..16 {
+                assert_eq!(kyoku[k], raw_kyoku[k], "kyoku[{k}]");
+            }
+            assert_eq!(kyoku[16][0], raw_kyoku[16][0]);
+            assert_eq!(kyoku[16][1], raw_kyoku[16][1]);
+            assert_eq!(kyoku[16][2], json!(raw_kyoku[16][2].as_array().unwrap()[..3]));
+        }
+    }
+
+    #[test]
+    fn yaku_stats_counts_fixtures() {
+        let mut stats = YakuStats::default();
+        stats.add_game(&parse_tenhou_json(include_str!("fixtures/dealer_tsumo.json")).unwrap());
+        stats.add_game(&parse_tenhou_json(include_str!("fixtures/chiitoitsu_ron.json")).unwrap());
+
+        assert_eq!(stats.agari, 2);
+        assert_eq!(stats.yaku.len(), 2);
+        let tenhou = &stats.yaku["天和"];
+        assert_eq!((tenhou.count, tenhou.yakuman), (1, true));
+        let chiitoitsu = &stats.yaku["七対子"];
+        assert_eq!((chiitoitsu.count, chiitoitsu.han, chiitoitsu.yakuman), (1, 2, false));
+    }
+
+    #[test]
+    fn yaku_stats_skips_zero_han_dora() {
+        let mut stats = YakuStats::default();
+        let agari = json!([0, 1, 0, "40符3飜5200点", "立直(1飜)", "ドラ(2飜)", "裏ドラ(0飜)"]);
+        stats.add_game(&json!({ "log": [[["和了", [5200, -5200, 0, 0], agari]]] }));
+
+        assert_eq!(stats.agari, 1);
+        assert!(!stats.yaku.contains_key("裏ドラ"), "{:?}", stats.yaku);
+        assert_eq!(stats.yaku["立直"].han, 1);
+        assert_eq!((stats.yaku["ドラ"].count, stats.yaku["ドラ"].han), (1, 2));
+    }
+
+    #[test]
+    fn anonymize_names_with_salt_is_stable_per_salt() {
+        let anonymize = |salt| {
+            let mut raw = json!({ "name": ["A", "B", "A", ""] });
+            anonymize_names(&mut raw, Some(salt));
+            names_of(&raw).into_iter().map(str::to_owned).collect::<Vec<_>>()
+        };
+        let names = anonymize("salt");
+        assert_eq!(names, anonymize("salt"));
+        assert_eq!(names[0], names[2]);
+        assert_ne!(names[0], names[1]);
+        assert!(names.iter().all(|name| name.len() == 16));
+        assert_ne!(names[0], anonymize("other salt")[0]);
+    }
+
+    #[test]
+    fn split_games_accepts_concatenated_logs() {
+        let games = split_games(include_str!("fixtures/two_games.json")).unwrap();
+        let expected: Vec<json::Value> = [
+            include_str!("fixtures/dealer_tsumo.json"),
+            include_str!("fixtures/chiitoitsu_ron.json"),
+        ]
+        .map(|log| json::from_str(log).unwrap())
+        .into();
+        assert_eq!(games, expected);
+    }
+
+    #[test]
+    fn split_games_accepts_an_array_of_logs() {
+        let body = include_str!("fixtures/two_games_array.json");
+        assert_eq!(split_games(body), split_games(include_str!("fixtures/two_games.json")));
+        // 多场比赛的文件不能按单个牌谱解析，转换时才会改用 split_games
+        assert!(parse_tenhou_json(body).is_err());
+    }
+
+    #[test]
+    fn split_games_rejects_single_games_and_non_logs() {
+        assert_eq!(split_games(include_str!("fixtures/dealer_tsumo.json")), None);
+        assert_eq!(split_games(r#"[{"log": []}, {"rule": {}}]"#), None);
+        assert_eq!(split_games("[]"), None);
+        assert_eq!(split_games("{\"type\":\"start_game\"}\n{\"type\":\"end_game\"}\n"), None);
+    }
+
+    #[test]
+    fn write_transcript_replays_fixtures() {
+        let events = convert_log(include_str!("fixtures/chiitoitsu_ron.json")).unwrap();
+        let mut out = vec![];
+        write_transcript(&mut out, &events).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "East 1, honba 0, riichi sticks 0, dora indicator F");
+        assert!(lines.contains(&"East (A) draws C, discards C (tsumogiri)"), "{out}");
+        let ron = "South (B) wins by ron from East (A) on C, deltas -1600 +1600 +0 +0";
+        assert!(lines.contains(&ron), "{out}");
+    }
+
+    #[test]
+    fn write_transcript_rejects_invalid_actor() {
+        let events = with_invalid_actor(include_str!("fixtures/chiitoitsu_ron.json"));
+        let mut out = vec![];
+        let err = write_transcript(&mut out, &events).unwrap_err().to_string();
+        assert!(err.contains("invalid actor 4"), "{err}");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn tile_sort_key_puts_red_fives_after_plain_fives() {
+        let mut hand = ["E", "5mr", "6m", "1s", "5m", "9p", "4m", "C", "5pr", "5p"];
+        hand.sort_by_key(|tile| tile_sort_key(tile));
+        assert_eq!(hand, ["4m", "5m", "5mr", "6m", "5p", "5pr", "9p", "1s", "E", "C"]);
+    }
+
+    #[test]
+    fn parse_hand_reads_compact_notation() {
+        assert_eq!(parse_hand("123m 0p 7z").unwrap(), ["1m", "2m", "3m", "5pr", "C"]);
+        assert_eq!(parse_hand("123m456p789s11z22z").unwrap().len(), 13);
+    }
+
+    #[test]
+    fn parse_hand_reads_mjai_tiles() {
+        let tiles = parse_hand(r#"["1m","2m","3m", "5pr", "C"]"#).unwrap();
+        assert_eq!(tiles, parse_hand("123m0p7z").unwrap());
+        assert_eq!(parse_hand(r#" [ "E" , "E" ] "#).unwrap(), ["E", "E"]);
+    }
+
+    #[test]
+    fn parse_hand_rejects_mixed_notation() {
+        let err = |hand| parse_hand(hand).unwrap_err().to_string();
+        let mixed = err(r#"["1m","23p"]"#);
+        assert!(mixed.contains(r#"token #2 "23p""#) && mixed.contains("compact"), "{mixed}");
+        assert!(err(r#"["1m","?","3m","4m"]"#).contains(r#"token #2 "?""#));
+        let mixed = err(r#"123m"4p""#);
+        assert!(mixed.contains("position 5") && mixed.contains("JSON array"), "{mixed}");
+        assert!(err("123mE").contains("position 5"));
+        assert!(err(r#"["1m","#).contains("not a JSON array"));
+    }
+
+    #[test]
+    fn parse_hand_rejects_invalid_hands() {
+        let err = |hand| parse_hand(hand).unwrap_err().to_string();
+        let expected = "unexpected `x` at position 4, expected a digit or one of m, p, s, z";
+        assert_eq!(err("123x"), expected);
+        assert!(err("m123").contains("`m` at position 1"));
+        assert!(err("123m45").contains("from position 5"));
+        assert!(err("18z").contains("`8z` at position 2"));
+        assert!(err("123456789m123456p").contains("15 tiles"));
+        assert!(err("123m").contains("3n+1 or 3n+2"));
+        assert!(err("11111m").contains("more than 4 1m"));
+        assert!(err("00m").contains("more than one 5mr"));
+    }
+
+    #[test]
+    fn shanten_counts_all_hand_shapes() {
+        let shanten_of = |hand| shanten(&parse_hand(hand).unwrap()).unwrap();
+        assert_eq!(shanten_of("123m456p789s11z22z"), 0);
+        assert_eq!(shanten_of("123m456p789s12345z"), 2);
+        // 七对子、国士无双和副露后的手牌
+        assert_eq!(shanten_of("11335577m99p11s22s"), -1);
+        assert_eq!(shanten_of("19m19p19s1234567z"), 0);
+        assert_eq!(shanten_of("11m"), -1);
+    }
+
+    #[test]
+    fn ukeire_counts_remaining_tiles() {
+        let ukeire_of = |hand| ukeire(&parse_hand(hand).unwrap()).unwrap();
+        let expected = Ukeire { shanten: 0, tiles: vec!["E".into(), "S".into()], count: 4 };
+        assert_eq!(ukeire_of("123m456p789s11z22z"), expected);
+        // 九莲宝灯听 9 种，手中已有 3 张的 1m、9m 只剩 1 张
+        let chuuren = ukeire_of("1112345678999m");
+        assert_eq!((chuuren.tiles.len(), chuuren.count), (9, 23));
+        assert_eq!(ukeire_of("19m19p19s1234567z").count, 39);
+        assert_eq!(ukeire_of("1234p").tiles, ["1p", "4p"]);
+        assert!(ukeire(&parse_hand("123m456p789s12345z").unwrap()).is_err());
+    }
+
+    #[test]
+    fn discard_ukeire_puts_best_discards_first() {
+        let options = discard_ukeire(&parse_hand("123m456p789s12345z").unwrap()).unwrap();
+        assert_eq!(options.len(), 5);
+        let (discard, best) = &options[0];
+        assert_eq!(discard, "E");
+        assert_eq!((best.shanten, best.count), (2, 12));
+        assert_eq!(best.tiles, ["S", "W", "N", "P"]);
+    }
+
+    #[test]
+    fn efficiency_decisions_compare_discards() {
+        let events = convert_log(include_str!("fixtures/chiitoitsu_ron.json")).unwrap();
+        let decisions = efficiency_decisions(&events).unwrap();
+        assert_eq!(decisions.len(), 1);
+        let decision = &decisions[0];
+        assert_eq!((decision["actor"].as_u64(), decision["turn"].as_u64()), (Some(0), Some(1)));
+        assert_eq!(decision["hand"].as_array().unwrap().len(), 14);
+        assert_eq!(decision["discard"], "C");
+        assert_eq!((decision["actual"]["shanten"].as_i64(), decision["actual"]["count"].as_u64()),
+                   (Some(3), Some(58)));
+        assert_eq!(decision["best_discards"], json!(["1s", "2s", "C"]));
+        assert_eq!(decision["best"]["count"], 58);
+    }
+
+    #[test]
+    fn normalize_names_uses_nfc() {
+        // か + 合成用濁点
+        let mut raw = json!({ "name": ["\u{304b}\u{3099}", "B", "C", "D"] });
+        normalize_names(&mut raw);
+        assert_eq!(names_of(&raw)[0], "\u{304c}");
+    }
+
+    /// 转换天凤牌谱，确认转换结果与原牌谱一致（见 [`verify_events`]）且亮出的牌没有超过
+    /// 一副牌（见 [`check_wall`]），返回序列化后的事件
+    fn convert_fixture(log: &str) -> Vec<json::Value> {
+        let raw = parse_tenhou_json(log).unwrap();
+        let events = convert_log(log).unwrap();
+        let mismatches = verify_events(&raw, &events).unwrap();
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+        let mismatches = check_wall(&events).unwrap();
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+        event_values(&events).unwrap()
+    }
+
+    /// 第 `index` 局（从 0 开始）从 `start_kyoku` 到 `end_kyoku` 的事件
+    fn kyoku_events(events: &[json::Value], index: usize) -> &[json::Value] {
+        let starts: Vec<_> = (0..events.len())
+            .filter(|&i| events[i]["type"] == "start_kyoku")
+            .collect();
+        let end = (starts[index]..events.len())
+            .find(|&i| events[i]["type"] == "end_kyoku")
+            .unwrap();
+        &events[starts[index]..=end]
+    }
+
+    fn types_of(events: &[json::Value]) -> Vec<&str> {
+        events.iter().map(|event| event["type"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn abortive_draws_have_no_payments() {
+        let log = include_str!("fixtures/abortive_draws.json");
+        let events = convert_fixture(log);
+
+        // 九种九牌：南家第一次摸牌后直接流局
+        let kyuushu = kyoku_events(&events, 0);
+        let expected = ["start_kyoku", "tsumo", "dahai", "tsumo", "ryukyoku", "end_kyoku"];
+        assert_eq!(types_of(kyuushu), expected);
+        assert_eq!(kyuushu[3]["actor"], 1);
+
+        // 四杠散了：四家各开了一个杠，第四个杠之后的打牌无人和了即流局，庄家连庄并积一本场
+        let suukaikan = kyoku_events(&events, 1);
+        assert_eq!((suukaikan[0]["kyoku"].as_u64(), suukaikan[0]["honba"].as_u64()),
+                   (Some(1), Some(1)));
+        let types = types_of(suukaikan);
+        let kans = types.iter().filter(|&&t| t == "ankan" || t == "daiminkan").count();
+        assert_eq!(kans, 4);
+        assert_eq!(types.iter().filter(|&&t| t == "dora").count(), 4);
+        assert_eq!(types[types.len() - 3..], ["dahai", "ryukyoku", "end_kyoku"]);
+
+        for ryukyoku in [&kyuushu[4], &suukaikan[types.len() - 2]] {
+            let deltas = &ryukyoku["deltas"];
+            assert!(deltas.is_null() || *deltas == json!([0, 0, 0, 0]), "{ryukyoku}");
+        }
+        assert!(is_incomplete_game(&parse_tenhou_json(log).unwrap(), GameLength::Tonpuu));
+    }
+
+
+    #[test]
+    fn kans_draw_rinshan_and_reveal_dora() {
+        let events = convert_fixture(include_str!("fixtures/three_kans.json"));
+        let find = |from: usize, kind: &str| {
+            from + events[from..].iter().position(|event| event["type"] == kind).unwrap()
+        };
+
+        let ankan = find(0, "ankan");
+        assert_eq!(events[ankan]["actor"], 0);
+        assert_eq!(events[ankan]["consumed"], json!(["7p", "7p", "7p", "7p"]));
+        let pon = find(0, "pon");
+        assert_eq!((events[pon]["actor"].as_u64(), events[pon]["target"].as_u64()),
+                   (Some(1), Some(0)));
+        assert_eq!(events[pon]["consumed"], json!(["E", "E"]));
+        let daiminkan = find(0, "daiminkan");
+        assert_eq!((events[daiminkan]["actor"].as_u64(), events[daiminkan]["target"].as_u64()),
+                   (Some(2), Some(1)));
+        assert_eq!(events[daiminkan]["pai"], "9s");
+        assert_eq!(events[daiminkan]["consumed"], json!(["9s", "9s", "9s"]));
+        let kakan = find(0, "kakan");
+        assert_eq!(events[kakan]["actor"], 1);
+        assert_eq!(events[kakan]["pai"], "E");
+        assert_eq!(events[kakan]["consumed"], json!(["E", "E", "E"]));
+
+        // 杠之后由本人摸岭上牌；暗杠的杠宝牌在打牌前翻开，大明杠和加杠的在岭上摸牌后的打牌之后翻开
+        assert_eq!(types_of(&events).iter().filter(|&&t| t == "dora").count(), 3);
+        for (kan, actor) in [(ankan, 0), (daiminkan, 2), (kakan, 1)] {
+            let rinshan = find(kan, "tsumo");
+            assert_eq!(events[rinshan]["actor"], actor);
+            let dahai = find(rinshan, "dahai");
+            assert_eq!(events[dahai]["actor"], actor);
+            if kan == ankan {
+                assert!(find(kan, "dora") < dahai);
+            } else {
+                assert_eq!(events[dahai + 1]["type"], "dora");
+            }
+        }
+    }
+
+
+    #[test]
+    fn dora_and_ura_markers_are_revealed_in_order() {
+        let events = convert_fixture(include_str!("fixtures/double_riichi_kans.json"));
+        let types = types_of(&events);
+        let find = |kind: &str| types.iter().position(|&t| t == kind).unwrap();
+
+        // 庄家第一次打牌即立直，立直宣言牌通过后才成立
+        assert_eq!(events[find("reach")]["actor"], 0);
+        assert_eq!(find("dahai"), find("reach") + 1);
+        assert_eq!(find("reach_accepted"), find("dahai") + 1);
+
+        // 大明杠的杠宝牌在岭上摸牌后的打牌之后翻开，暗杠的在打牌之前翻开
+        let doras: Vec<_> = (0..events.len()).filter(|&i| types[i] == "dora").collect();
+        assert_eq!(doras.len(), 2);
+        let (daiminkan, ankan) = (find("daiminkan"), find("ankan"));
+        assert!(daiminkan < doras[0] && doras[0] < ankan);
+        assert_eq!(types[doras[0] - 1], "dahai");
+        assert_eq!(events[doras[0] - 1]["actor"], 2);
+        assert!(ankan < doras[1] && types[ankan..doras[1]].iter().all(|&t| t != "dahai"));
+        let marker = |i: usize| events[i]["dora_marker"].as_str().unwrap();
+        let markers: Vec<_> = doras.iter().map(|&i| marker(i)).collect();
+        assert_eq!(markers, ["7m", "9p"]);
+
+        // 里宝牌指示牌只在立直者和了时随 hora 公开，张数与宝牌指示牌相同
+        let hora = &events[find("hora")];
+        assert_eq!((hora["actor"].as_u64(), hora["target"].as_u64()), (Some(0), Some(0)));
+        assert_eq!(hora["ura_markers"], json!(["N", "F", "8s"]));
+        assert!(events.iter().filter(|e| e["type"] != "hora").all(|e| e["ura_markers"].is_null()));
+    }
+
+
+    #[test]
+    fn red_fives_keep_their_color() {
+        let log = include_str!("fixtures/red_fives.json");
+        let events = convert_fixture(log);
+
+        // 天凤的 51~53 是赤五，摸切、手切和放铳的都应保留 `r`
+        let tiles = |kind: &str| -> Vec<_> {
+            events
+                .iter()
+                .filter(|event| event["type"] == kind && event["pai"].as_str().unwrap().len() == 3)
+                .map(|event| (event["actor"].as_u64().unwrap(), event["pai"].as_str().unwrap()))
+                .collect()
+        };
+        assert_eq!(tiles("tsumo"), [(0, "5mr"), (1, "5pr"), (2, "5sr")]);
+        assert_eq!(tiles("dahai"), [(0, "5mr"), (1, "5pr"), (2, "5sr")]);
+        let hora = events.iter().find(|event| event["type"] == "hora").unwrap();
+        assert_eq!((hora["actor"].as_u64(), hora["target"].as_u64()), (Some(3), Some(2)));
+        assert_eq!(hora["deltas"], json!([0, 0, -3900, 3900]));
+
+        // 和了牌上的赤五也计为一番
+        let mut stats = YakuStats::default();
+        stats.add_game(&parse_tenhou_json(log).unwrap());
+        assert_eq!((stats.yaku["赤ドラ"].count, stats.yaku["赤ドラ"].han), (1, 1));
+    }
+
+
+    #[test]
+    fn chankan_interrupts_the_kakan() {
+        let log = include_str!("fixtures/chankan.json");
+        let events = convert_fixture(log);
+        let types = types_of(&events);
+
+        // 加杠的牌被抢和：没有岭上摸牌，也不翻开杠宝牌
+        let kakan = types.iter().position(|&t| t == "kakan").unwrap();
+        assert_eq!((events[kakan]["actor"].as_u64(), events[kakan]["pai"].as_str()),
+                   (Some(1), Some("3p")));
+        assert_eq!(types[kakan + 1..], ["hora", "end_kyoku", "end_game"]);
+        let hora = &events[kakan + 1];
+        assert_eq!((hora["actor"].as_u64(), hora["target"].as_u64()), (Some(3), Some(1)));
+        assert_eq!(hora["deltas"], json!([0, -1300, 0, 1300]));
+        assert!(!types.contains(&"dora"));
+
+        let mut stats = YakuStats::default();
+        stats.add_game(&parse_tenhou_json(log).unwrap());
+        assert_eq!(stats.yaku["槍槓"].count, 1);
+    }
+
+
+    #[test]
+    fn riichi_stick_is_paid_once_the_discard_passes() {
+        let events = convert_fixture(include_str!("fixtures/riichi_sticks.json"));
+
+        // 立直宣言牌被荣和：立直不成立，没有 reach_accepted，也不支付立直棒
+        let ronned = kyoku_events(&events, 0);
+        let types = types_of(ronned);
+        let reach = types.iter().position(|&t| t == "reach").unwrap();
+        assert_eq!(types[reach..], ["reach", "dahai", "hora", "end_kyoku"]);
+        assert_eq!((ronned[reach + 2]["actor"].as_u64(), ronned[reach + 2]["target"].as_u64()),
+                   (Some(2), Some(1)));
+        assert_eq!(ronned[reach + 2]["deltas"], json!([0, -2000, 2000, 0]));
+
+        // 立直宣言牌通过后才支付立直棒，和了者连同立直棒一起取得；扣除立直棒后的最终得点
+        // 由 verify_events 与牌谱的 `sc` 对照
+        let passed = kyoku_events(&events, 1);
+        assert_eq!(passed[0]["scores"], json!([25000, 23000, 27000, 25000]));
+        assert_eq!(passed[0]["kyotaku"], 0);
+        let types = types_of(passed);
+        let reach = types.iter().position(|&t| t == "reach").unwrap();
+        assert_eq!(types[reach..reach + 4], ["reach", "dahai", "reach_accepted", "tsumo"]);
+        assert_eq!(passed[reach + 2]["actor"], 2);
+        let hora = passed.iter().find(|event| event["type"] == "hora").unwrap();
+        assert_eq!(hora["deltas"], json!([-1300, 0, 0, 2300]));
+    }
+
+
+    #[test]
+    fn exhaustive_draws_pay_nagashi_mangan_and_tenpai() {
+        let log = include_str!("fixtures/nagashi_mangan.json");
+        let events = convert_fixture(log);
+        let hands = final_hands(&convert_log(log).unwrap()).unwrap();
+        let ryukyoku = |kyoku: &[json::Value]| kyoku[kyoku.len() - 2].clone();
+
+        // 流局满贯：北家摸切了 17 张幺九牌，按满贯自摸收取，不再有听牌费
+        let nagashi = kyoku_events(&events, 0);
+        assert_eq!(types_of(nagashi).iter().filter(|&&t| t == "tsumo").count(), 70);
+        let discards: Vec<_> = nagashi
+            .iter()
+            .filter(|event| event["type"] == "dahai" && event["actor"] == 3)
+            .map(|event| event["pai"].as_str().unwrap())
+            .collect();
+        assert_eq!(discards.len(), 17);
+        assert!(discards.iter().all(|pai| pai.len() == 1 || pai.starts_with(['1', '9'])));
+        assert_eq!(ryukyoku(nagashi)["type"], "ryukyoku");
+        assert_eq!(ryukyoku(nagashi)["deltas"], json!([-4000, -2000, -2000, 8000]));
+
+        // 荒牌流局：听牌的两家各从不听的两家收取 1500 点，与结束时的手牌一致
+        let tenpai_draw = kyoku_events(&events, 1);
+        assert_eq!((tenpai_draw[0]["kyoku"].as_u64(), tenpai_draw[0]["honba"].as_u64()),
+                   (Some(2), Some(1)));
+        let tenpai: Vec<_> = (0..4)
+            .map(|seat| {
+                let tehai = hands[1]["hands"][seat]["tehai"].as_array().unwrap();
+                let tiles: Vec<_> = tehai.iter().map(|tile| tile.as_str().unwrap()).collect();
+                shanten(&tiles).unwrap() == 0
+            })
+            .collect();
+        assert_eq!(tenpai, [false, true, true, false]);
+        assert_eq!(ryukyoku(tenpai_draw)["deltas"], json!([-1500, 1500, 1500, -1500]));
+    }
+
+
+    #[test]
+    fn honba_and_riichi_sticks_carry_over() {
+        let events = convert_fixture(include_str!("fixtures/dealer_renchan.json"));
+        let starts: Vec<_> = events.iter().filter(|e| e["type"] == "start_kyoku").collect();
+        let field = |name: &str| -> Vec<_> { starts.iter().map(|s| s[name].as_u64()).collect() };
+
+        // 庄家和了与中途流局都连庄并积一本场，中途流局时的立直棒留到下一局
+        assert_eq!(field("kyoku"), [Some(1); 3]);
+        assert_eq!(field("honba"), [Some(0), Some(1), Some(2)]);
+        assert_eq!(field("kyotaku"), [Some(0), Some(0), Some(1)]);
+        let aborted = kyoku_events(&events, 1);
+        let types = types_of(aborted);
+        assert_eq!(types[types.len() - 4..], ["reach_accepted", "tsumo", "ryukyoku", "end_kyoku"]);
+        assert_eq!(starts[2]["scores"], json!([27900, 22100, 24000, 25000]));
+
+        // 二本场的自摸每家多付 200 点，和了者另外取得上一局留下的立直棒
+        let hora = kyoku_events(&events, 2).iter().find(|e| e["type"] == "hora").unwrap();
+        assert_eq!(hora["deltas"], json!([5500, -1500, -1500, -1500]));
+    }
+
+
+    #[test]
+    fn multi_ron_follows_the_recorded_winners() {
+        let horas = |log: &str| -> Vec<_> {
+            let events = convert_fixture(log);
+            let types = types_of(&events);
+            let first = types.iter().position(|&t| t == "hora").unwrap();
+            assert_eq!(types[first - 1], "dahai");
+            events[first..]
+                .iter()
+                .take_while(|event| event["type"] == "hora")
+                .map(|event| {
+                    let [actor, target] = ["actor", "target"].map(|k| event[k].as_u64().unwrap());
+                    (actor, target, event["deltas"].clone())
+                })
+                .collect()
+        };
+
+        // 南家和西家听同一张牌：双响时两家都和了并各自结算，头跳时只有按打牌顺序最近的南家和了
+        let double_ron = horas(include_str!("fixtures/double_ron.json"));
+        let expected = [(1, 0, json!([-2000, 2000, 0, 0])), (2, 0, json!([-1300, 0, 1300, 0]))];
+        assert_eq!(double_ron, expected);
+        let head_bump = horas(include_str!("fixtures/head_bump.json"));
+        assert_eq!(head_bump, [(1, 0, json!([-2000, 2000, 0, 0]))]);
+    }
+
+
+    #[test]
+    fn disconnected_seat_keeps_its_seat() {
+        // 西家第一巡后掉线，之后由系统代为摸切，牌谱中的名字为空
+        let events = convert_fixture(include_str!("fixtures/disconnected_seat.json"));
+        assert_eq!(events[0]["names"], json!(["A", "B", "player2", "D"]));
+
+        let discards: Vec<_> = events
+            .iter()
+            .filter(|event| event["type"] == "dahai" && event["actor"] == 2)
+            .map(|event| event["tsumogiri"].as_bool().unwrap())
+            .collect();
+        assert_eq!(discards, [false, true, true, true]);
+
+        for (index, oya, winner, target) in [(0, 0, 3, 2), (1, 1, 1, 1)] {
+            let kyoku = kyoku_events(&events, index);
+            assert_eq!(kyoku[0]["oya"], oya);
+            let hora = kyoku.iter().find(|event| event["type"] == "hora").unwrap();
+            assert_eq!((hora["actor"].as_u64(), hora["target"].as_u64()),
+                       (Some(winner), Some(target)));
+        }
+    }
+
+
+    #[test]
+    fn double_riichi_needs_an_uninterrupted_first_turn() {
+        let log = include_str!("fixtures/double_riichi.json");
+        let events = convert_fixture(log);
+        // 确认立直宣言是该家本局的第一次打牌，返回在此之前是否有人鸣牌
+        let called_before_riichi = |kyoku: &[json::Value], actor: u64| {
+            let dahai = kyoku.iter().position(|e| e["type"] == "dahai" && e["actor"] == actor);
+            let reach = kyoku.iter().position(|e| e["type"] == "reach");
+            assert_eq!(reach.map(|i| i + 1), dahai);
+            kyoku[..dahai.unwrap()].iter().any(|e| e["type"] == "pon")
+        };
+
+        // 庄家第一次打牌即立直，是两立直
+        assert!(!called_before_riichi(kyoku_events(&events, 0), 0));
+        // 西家的第一次打牌也是立直宣言，但之前南家碰了牌，只是普通的立直
+        assert!(called_before_riichi(kyoku_events(&events, 1), 2));
+
+        let mut stats = YakuStats::default();
+        stats.add_game(&parse_tenhou_json(log).unwrap());
+        assert_eq!((stats.yaku["ダブル立直"].count, stats.yaku["ダブル立直"].han), (1, 2));
+        assert_eq!((stats.yaku["立直"].count, stats.yaku["立直"].han), (1, 1));
+        let hora = kyoku_events(&events, 0).iter().find(|e| e["type"] == "hora").unwrap();
+        assert_eq!(hora["deltas"], json!([4900, -3900, 0, 0]));
+    }
+
+
+    #[test]
+    fn ippatsu_is_broken_by_a_call() {
+        let log = include_str!("fixtures/ippatsu.json");
+        let events = convert_fixture(log);
+        // 确认立直者在立直后的下一次摸牌自摸和了，返回其间的鸣牌数
+        let calls_before_tsumo = |kyoku: &[json::Value], actor: u64| {
+            let types = types_of(kyoku);
+            let reach = types.iter().position(|&t| t == "reach").unwrap();
+            assert_eq!(kyoku[reach]["actor"], actor);
+            let tsumo = (reach..kyoku.len())
+                .find(|&i| types[i] == "tsumo" && kyoku[i]["actor"] == actor)
+                .unwrap();
+            assert_eq!(types[tsumo + 1], "hora");
+            let calls = ["chi", "pon", "daiminkan", "kakan", "ankan"];
+            types[reach..tsumo].iter().filter(|&&t| calls.contains(&t)).count()
+        };
+
+        // 东一局南家立直后北家碰了牌，一发消失；东二局西家立直后无人鸣牌，一发自摸
+        assert_eq!(calls_before_tsumo(kyoku_events(&events, 0), 1), 1);
+        assert_eq!(calls_before_tsumo(kyoku_events(&events, 1), 2), 0);
+
+        let raw = parse_tenhou_json(log).unwrap();
+        let yaku = |kyoku: usize| -> Vec<_> {
+            let agari = raw["log"][kyoku][16][2].as_array().unwrap();
+            agari[4..].iter().map(|yaku| yaku.as_str().unwrap()).collect()
+        };
+        assert!(!yaku(0).contains(&"一発(1飜)"), "{:?}", yaku(0));
+        assert!(yaku(1).contains(&"一発(1飜)"), "{:?}", yaku(1));
+    }
+
+
+    #[test]
+    fn tenhou_and_chiihou_need_an_uninterrupted_first_draw() {
+        // 确认和了者在本局的第一次摸牌时自摸和了，返回在此之前是否有人鸣牌
+        let called_before_win = |kyoku: &[json::Value], actor: u64| {
+            let hora = kyoku.iter().position(|e| e["type"] == "hora").unwrap();
+            assert_eq!((kyoku[hora]["actor"].as_u64(), kyoku[hora]["target"].as_u64()),
+                       (Some(actor), Some(actor)));
+            let draws = kyoku[..hora].iter().filter(|e| e["type"] == "tsumo");
+            assert_eq!(draws.filter(|e| e["actor"] == actor).count(), 1);
+            kyoku[..hora].iter().any(|e| e["type"] == "pon")
+        };
+
+        // 天和：庄家的第一次摸牌
+        let tenhou_log = include_str!("fixtures/dealer_tsumo.json");
+        let events = convert_fixture(tenhou_log);
+        assert!(!called_before_win(kyoku_events(&events, 0), 0));
+        // 地和：南家在无人鸣牌时的第一次摸牌；东二局庄家打出的牌被碰之后，A 在第一次摸牌时
+        // 自摸和了也不是地和
+        let chiihou_log = include_str!("fixtures/chiihou.json");
+        let events = convert_fixture(chiihou_log);
+        assert!(!called_before_win(kyoku_events(&events, 0), 1));
+        assert!(called_before_win(kyoku_events(&events, 1), 0));
+
+        let mut stats = YakuStats::default();
+        for log in [tenhou_log, chiihou_log] {
+            stats.add_game(&parse_tenhou_json(log).unwrap());
+        }
+        assert_eq!(stats.agari, 3);
+        for yakuman in ["天和", "地和"] {
+            assert_eq!((stats.yaku[yakuman].count, stats.yaku[yakuman].yakuman), (1, true));
+        }
+        assert_eq!(stats.yaku["門前清自摸和"].count, 1);
+    }
+
+
+    #[test]
+    fn four_melds_win_on_rinshan() {
+        let log = include_str!("fixtures/four_melds_rinshan.json");
+        let events = convert_fixture(log);
+        let types = types_of(&events);
+
+        // 南家吃、碰、大明杠、碰之后单骑听 8m，加杠后摸到岭上的 8m 自摸：
+        // 和了前没有打牌，加杠的杠宝牌也不再翻开，只有大明杠的一张
+        let kakan = types.iter().position(|&t| t == "kakan").unwrap();
+        assert_eq!((events[kakan]["actor"].as_u64(), events[kakan]["pai"].as_str()),
+                   (Some(1), Some("4p")));
+        assert_eq!(types[kakan + 1..], ["tsumo", "hora", "end_kyoku", "end_game"]);
+        assert_eq!((events[kakan + 1]["actor"].as_u64(), events[kakan + 1]["pai"].as_str()),
+                   (Some(1), Some("8m")));
+        let hora = &events[kakan + 2];
+        assert_eq!((hora["actor"].as_u64(), hora["target"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(hora["deltas"], json!([-1600, 3200, -800, -800]));
+        let doras: Vec<_> = (0..events.len()).filter(|&i| types[i] == "dora").collect();
+        assert_eq!(doras.len(), 1);
+        assert!(types.iter().position(|&t| t == "daiminkan").unwrap() < doras[0]);
+        assert_eq!(types[doras[0] - 1], "dahai");
+
+        // 四副露后手牌只剩单骑的一张，其他三家仍是 13 张
+        let hands = final_hands(&convert_log(log).unwrap()).unwrap();
+        let winner = &hands[0]["hands"][1];
+        assert_eq!(winner["tehai"], json!(["8m"]));
+        let melds = winner["melds"].as_array().unwrap();
+        let melds: Vec<_> = melds.iter().map(|meld| meld["type"].as_str().unwrap()).collect();
+        assert_eq!(melds, ["chi", "pon", "daiminkan", "kakan"]);
+        for seat in [0, 2, 3] {
+            assert_eq!(hands[0]["hands"][seat]["tehai"].as_array().unwrap().len(), 13);
+        }
+        assert_eq!(hands[0]["result"][0]["pai"], "8m");
+
+        let mut stats = YakuStats::default();
+        stats.add_game(&parse_tenhou_json(log).unwrap());
+        assert_eq!(stats.yaku["嶺上開花"].count, 1);
+    }
+}
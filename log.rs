@@ -0,0 +1,156 @@
+//! 输出到 stderr 的日志。
+//!
+//! [`log!`] 输出进度信息，[`set_quiet`] 后不输出；[`verbose!`] 只在 [`set_verbose`] 后输出；
+//! [`warning!`] 和 [`error!`] 总是输出。每条日志交给 [`set_sink`] 设置的 [`Sink`] 写出，
+//! 默认为 [`PlainSink`]。
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{Local, SubsecRound};
+use serde_json as json;
+
+/// 输出一行进度信息，指定 `--quiet` 时不输出。真正的错误用 [`error!`] 输出。
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+/// 只在指定 `--verbose` 时输出的 [`log!`]
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+/// 不受 `--quiet` 影响的警告
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+/// 不受 `--quiet` 影响的错误
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static SINK: OnceLock<Box<dyn Sink>> = OnceLock::new();
+
+thread_local! {
+    /// 当前线程正在处理的输入，作为 [`Record::file`] 交给 [`Sink`]
+    static CURRENT_FILE: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// 日志的级别
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// 一条日志
+pub struct Record<'a> {
+    pub level: Level,
+    /// 输出日志的线程正在处理的输入，见 [`set_current_file`]
+    pub file: Option<&'a Path>,
+    pub message: fmt::Arguments<'a>,
+}
+
+/// 日志的输出目标。会被多个线程同时调用，每条日志应一次写出，以免多行交错。
+pub trait Sink: Send + Sync {
+    fn write(&self, record: &Record<'_>);
+}
+
+/// 默认的输出目标：进度信息带有时间戳，警告以 `warning: ` 开头，错误原样输出
+pub struct PlainSink;
+
+impl Sink for PlainSink {
+    fn write(&self, record: &Record<'_>) {
+        match record.level {
+            Level::Debug | Level::Info => {
+                let now = Local::now().naive_local().trunc_subsecs(3);
+                eprintln!("[{now}] {}", record.message);
+            }
+            Level::Warn => eprintln!("warning: {}", record.message),
+            Level::Error => eprintln!("{}", record.message),
+        }
+    }
+}
+
+/// `--log-format json`：每条日志输出一行含 `level`、`timestamp`、`file`、`message` 的 JSON
+pub struct JsonSink;
+
+impl Sink for JsonSink {
+    fn write(&self, record: &Record<'_>) {
+        let line = json::json!({
+            "level": record.level.name(),
+            "timestamp": Local::now().to_rfc3339(),
+            "file": record.file,
+            "message": record.message.to_string(),
+        });
+        eprintln!("{line}");
+    }
+}
+
+/// 设置日志的输出目标，只能在输出第一条日志之前设置一次，之后的设置会被忽略
+pub fn set_sink(sink: Box<dyn Sink>) {
+    drop(SINK.set(sink));
+}
+
+/// 设置是否不输出 [`log!`] 的进度信息
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// 设置是否输出 [`verbose!`] 的信息
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// 设置当前线程正在处理的输入，处理完后以 `None` 清除
+pub fn set_current_file(input_path: Option<&Path>) {
+    CURRENT_FILE.set(input_path.map(Path::to_path_buf));
+}
+
+/// 按级别过滤后交给输出目标，由 [`log!`] 等宏调用
+pub fn write(level: Level, message: fmt::Arguments<'_>) {
+    let enabled = match level {
+        Level::Debug => VERBOSE.load(Ordering::Relaxed),
+        Level::Info => !QUIET.load(Ordering::Relaxed),
+        Level::Warn | Level::Error => true,
+    };
+    if !enabled {
+        return;
+    }
+
+    CURRENT_FILE.with_borrow(|file| {
+        let record = Record { level, file: file.as_deref(), message };
+        SINK.get_or_init(|| Box::new(PlainSink)).write(&record);
+    });
+}
@@ -0,0 +1,193 @@
+//! 转换的输入来源：单个牌谱文件、目录、zip 压缩包、天凤牌谱 URL 列表、需要下载的天凤牌谱 URL，
+//! 或者在命令行中直接给出的牌谱 JSON。
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use zip::ZipArchive;
+
+use crate::download::{self, DownloadOptions, tenhou_log_id};
+use crate::error;
+
+/// 命令行中 `<INPUT>` 所指的输入
+pub enum LogSource {
+    /// 单个牌谱文件。不存在的路径也归为此类，转换时报告无法打开文件
+    File(PathBuf),
+    /// 含有牌谱的目录
+    Directory(PathBuf),
+    /// 含有牌谱的 zip 压缩包
+    Zip(PathBuf),
+    /// 逐行列出天凤牌谱 URL 的 `.txt` 文件，见 [`is_url_list`]
+    UrlList(PathBuf),
+    /// 需要下载的天凤牌谱，为牌谱 ID
+    Tenhou(String),
+    /// `--inline` 给出的牌谱 JSON，`-` 表示从 stdin 读取，见 [`read_inline`]
+    Inline(String),
+}
+
+impl LogSource {
+    /// 判断 `input` 是哪种输入。只有本地不存在的路径才会被当作天凤牌谱 URL 或牌谱 ID
+    pub fn from_input(input: &Path) -> Self {
+        if !input.exists() {
+            return match input.to_str().and_then(tenhou_log_id) {
+                Some(log_id) => Self::Tenhou(log_id.to_owned()),
+                None => Self::File(input.to_path_buf()),
+            };
+        }
+
+        let input = input.to_path_buf();
+        if input.is_dir() {
+            Self::Directory(input)
+        } else if is_zip_extension(&input) {
+            Self::Zip(input)
+        } else if is_url_list(&input) {
+            Self::UrlList(input)
+        } else {
+            Self::File(input)
+        }
+    }
+}
+
+/// 读取 `--inline` 给出的牌谱 JSON：`-` 时从 stdin 读入全部内容，否则原样返回，不会截断
+pub fn read_inline(inline: &str) -> Result<String> {
+    if inline != "-" {
+        return Ok(inline.to_owned());
+    }
+    let mut body = String::new();
+    io::stdin().read_to_string(&mut body).context("failed to read log from stdin")?;
+    Ok(body)
+}
+
+/// 不解压、逐个读取其中牌谱的 zip 压缩包
+pub struct ZipLogs {
+    archive: ZipArchive<BufReader<File>>,
+}
+
+impl ZipLogs {
+    pub fn open(zip_path: &Path) -> Result<Self> {
+        let file = File::open(zip_path)
+            .with_context(|| format!("failed to open zip archive: {zip_path:?}"))?;
+        let archive = ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("failed to read zip archive: {zip_path:?}"))?;
+        Ok(Self { archive })
+    }
+
+    /// 压缩包中的条目数，包括目录和不像牌谱的条目
+    pub fn entry_count(&self) -> usize {
+        self.archive.len()
+    }
+
+    /// 不解压地统计会被 [`Self::read_log`] 读出的条目数。
+    /// 无法读取的条目也计入，它们读取时会作为错误报告
+    pub fn count_logs(&mut self) -> usize {
+        (0..self.archive.len())
+            .filter(|&index| {
+                self.archive.by_index_raw(index).map_or(true, |entry| {
+                    !entry.is_dir()
+                        && entry.enclosed_name().is_none_or(|name| is_log_extension(&name))
+                })
+            })
+            .count()
+    }
+
+    /// 读取第 `index` 个条目，返回条目路径和内容。
+    /// 目录以及扩展名不像牌谱的条目返回 `None`。
+    pub fn read_log(&mut self, index: usize) -> Result<Option<(PathBuf, Vec<u8>)>> {
+        let mut entry = self
+            .archive
+            .by_index(index)
+            .with_context(|| format!("failed to read entry #{index}"))?;
+        if entry.is_dir() {
+            return Ok(None);
+        }
+        let name = entry
+            .enclosed_name()
+            .with_context(|| format!("entry has an unsafe path: {:?}", entry.name()))?;
+        if !is_log_extension(&name) {
+            return Ok(None);
+        }
+
+        let mut body = Vec::new();
+        entry
+            .read_to_end(&mut body)
+            .with_context(|| format!("failed to read entry {name:?}"))?;
+        Ok(Some((name, body)))
+    }
+}
+
+pub fn is_log_extension(path: &Path) -> bool {
+    // 只处理特定扩展名的文件，例如 .log、.txt 等
+    // 这里可以根据你的需求调整
+    path.extension().is_none_or(|ext| ext == "json" || ext == "txt" || ext == "gz")
+}
+
+pub fn is_zip_extension(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "zip")
+}
+
+/// 判断 `path` 是否是逐行列出天凤牌谱 URL（或牌谱 ID）的 `.txt` 文件，而不是牌谱 JSON。
+/// 与 [`feed_url_list`] 一样忽略以 `#` 开头的注释行，只根据第一个非空、非注释的行判断。
+pub fn is_url_list(path: &Path) -> bool {
+    if path.extension().is_none_or(|ext| ext != "txt") {
+        return false;
+    }
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .is_some_and(|line| tenhou_log_id(line.trim()).is_some())
+}
+
+/// URL 列表中会提交的行数，即非空、非注释的行数，用于 `--progress` 的总数。
+/// 无法读取时返回 1，与 [`feed_url_list`] 报告的一个错误对应
+pub fn count_url_list_entries(list_path: &Path) -> usize {
+    fs::read_to_string(list_path).map_or(1, |content| {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .count()
+    })
+}
+
+/// 下载 URL 列表 `list_path` 中的每个牌谱，并通过 `on_file` 提交转换。
+/// 空行和以 `#` 开头的行会被忽略，无法识别或下载失败的行逐行报错，返回出错的行数。
+pub fn feed_url_list(
+    list_path: &Path,
+    output_dir: &Path,
+    download: &DownloadOptions,
+    on_file: &mut dyn FnMut(&Path, &Path),
+) -> usize {
+    let content = match fs::read_to_string(list_path) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("error reading URL list {list_path:?}: {e}");
+            return 1;
+        }
+    };
+
+    let mut error_count = 0;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = tenhou_log_id(line)
+            .with_context(|| format!("not a tenhou log URL or log ID: {line:?}"))
+            .and_then(|log_id| download::tenhou_log(log_id, download));
+        match result {
+            Ok(path) => on_file(&path, output_dir),
+            Err(e) => {
+                error_count += 1;
+                error!("error processing {list_path:?} line {}: {e:#}", i + 1);
+            }
+        }
+    }
+    error_count
+}
@@ -95,30 +95,78 @@
 mod download;
 mod log;
 mod log_source;
-mod opts;
-mod render;
-mod review;
-mod softmax;
-mod state;
-mod tactics;
-mod tehai;
-
-use crate::log_source::LogSource;
-use crate::opts::{AkochanOptions, Engine, InputOptions, MortalOptions, Options, OutputOptions};
-use crate::render::View;
-use crate::review::{Review, akochan, mortal};
-use chrono::SubsecRound;
+
+use crate::download::{DownloadOptions, tenhou_log_id};
+use crate::log_source::{
+    LogSource, ZipLogs, count_url_list_entries, feed_url_list, is_log_extension, is_url_list,
+    is_zip_extension,
+};
+
+use convlog::mjai::Event;
 use convlog::tenhou::{GameLength, Log, RawLog};
-use convlog::tenhou_to_mjai;
-use std::fs::{self, File, ReadDir};
-use std::io;
+use mjai_reviewer::{
+    BatchStats, ContentHasher, ConvertFailure, ConvertStats, PlayerTendencies, SANMA_UNSUPPORTED,
+    Ukeire, YakuStats, anonymize_names, check_wall, convert_log, convert_raw_log, discard_ukeire,
+    efficiency_decisions, is_incomplete_game, is_sanma, mjai_event_schema, mjai_to_tenhou,
+    normalize_names, parse_hand, parse_mjai_events, parse_tenhou_json, parse_tenhou_json_lenient,
+    parse_tenhou_reader, shanten, split_games, ukeire, verify_events, write_events,
+    write_events_array, write_final_hands, write_transcript,
+};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, IsTerminal};
 use std::io::prelude::*;
+use std::iter;
+use std::mem;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, bail, ensure};
-use clap::{Parser, ValueEnum};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use flate2::read::MultiGzDecoder;
 use serde_json as json;
-use chrono::Local;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, SubsecRound, TimeDelta};
+
+/// 结束时的汇总是否带颜色
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Plain,
+    /// One JSON object per line with level, timestamp, file and message
+    /// fields.
+    Json,
+}
+
+/// 汇总中使用的 ANSI 前景色
+#[derive(Clone, Copy)]
+enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+}
+
+/// 启用颜色时给 `value` 加上 ANSI 转义序列，否则原样输出
+fn paint(value: impl fmt::Display, color: Color) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{value}\x1b[0m", color as u8)
+    } else {
+        value.to_string()
+    }
+}
 
 macro_rules! canonicalize {
     ($path:ident) => {{
@@ -137,144 +185,2926 @@ macro_rules! canonicalize {
     }};
 }
 
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
 enum ReportOutput {
     File(PathBuf),
     Stdout,
+    /// `--concat` 指定的文件，见 [`ConcatOutput`]
+    Concat,
 }
 
-fn process_file(input_path: &Path, output_dir: &Path) -> Result<()> {
-    log!("processing file: {:?}", input_path);
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// One mjai event per line.
+    #[default]
+    Ndjson,
+    /// A single JSON array of mjai events.
+    Array,
+    /// Instead of the events, one JSON record per line for every kyoku with
+    /// each player's final hand and melds, the winning tile and the result.
+    Hands,
+}
 
-    // 读取文件内容
-    let mut file = File::open(input_path)
-        .with_context(|| format!("failed to open file: {:?}", input_path))?;
-    let mut body = String::new();
-    file.read_to_string(&mut body)
-        .with_context(|| format!("failed to read file: {:?}", input_path))?;
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum GameLengthFilter {
+    /// Convert every log.
+    #[default]
+    All,
+    /// Only east-only (tonpuusen) games.
+    Tonpuusen,
+    /// Only east-south (hanchan) games.
+    Hanchan,
+}
+
+impl GameLengthFilter {
+    const fn matches(self, game_length: GameLength) -> bool {
+        match self {
+            Self::All => true,
+            Self::Tonpuusen => matches!(game_length, GameLength::Tonpuu),
+            Self::Hanchan => matches!(game_length, GameLength::Hanchan),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum RoomFilter {
+    /// Convert every log, including ones whose room is unknown.
+    #[default]
+    All,
+    /// Only ippan (一般) room games.
+    Ippan,
+    /// Only joukyuu (上級) room games.
+    Joukyuu,
+    /// Only tokujou (特上) room games.
+    Tokujou,
+    /// Only houou (鳳凰) room games.
+    Houou,
+}
+
+impl RoomFilter {
+    /// 牌谱 `rule.disp`（如 `鳳南喰赤`）中表示该房间的字
+    const fn marker(self) -> Option<char> {
+        match self {
+            Self::All => None,
+            Self::Ippan => Some('般'),
+            Self::Joukyuu => Some('上'),
+            Self::Tokujou => Some('特'),
+            Self::Houou => Some('鳳'),
+        }
+    }
 
-    // 解析 RawLog（原来是从 json 解析的）
-    let raw_log: RawLog = json::from_str(&body)
-        .with_context(|| format!("failed to parse tenhou.net/6 log from file: {:?}", input_path))?;
+    /// 无法从牌谱中确定房间时，只有 `All` 匹配
+    fn matches(self, raw: &json::Value) -> bool {
+        let Some(marker) = self.marker() else {
+            return true;
+        };
+        raw["rule"]["disp"].as_str().is_some_and(|disp| disp.contains(marker))
+    }
+}
 
-    // convert from RawLog to Log
-    let log = Log::try_from(raw_log).context("invalid log")?;
+/// `--strict`：平时只会警告或默认处理的牌谱问题也作为错误
+fn check_strict(raw: &json::Value, input_path: &Path) -> Result<()> {
+    ensure!(logged_start_time(raw, input_path).is_some(),
+            "no game timestamp in log or file name (--strict)");
 
-    // convert from tenhou::Log to Vec<mjai::Event>
-    let begin_convert_log = Local::now();
-    log!("converting {:?} to mjai events...", input_path.file_name().unwrap_or_default());
-    let events = tenhou_to_mjai(&log)
-        .with_context(|| format!("failed to convert {:?} into mjai format", input_path))?;
+    let disp = raw["rule"]["disp"].as_str().unwrap_or_default();
+    let rooms = [RoomFilter::Ippan, RoomFilter::Joukyuu, RoomFilter::Tokujou, RoomFilter::Houou];
+    ensure!(rooms.iter().filter_map(|room| room.marker()).any(|m| disp.contains(m)),
+            "cannot determine the room from the rule name {disp:?} (--strict)");
+    Ok(())
+}
 
-    // 创建输出文件名（保持原文件名，但可以修改扩展名）
-    let output_filename = input_path
-        .file_stem()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string() + ".json";
+/// 单个文件的处理结果
+enum FileOutcome {
+    Converted(ConvertedFile),
+    /// 不符合过滤条件，没有转换
+    Filtered,
+    /// 输出文件已是最新，没有重新转换
+    Skipped,
+    /// 与本次运行中已处理的另一个文件内容相同，没有转换
+    Duplicate {
+        original: PathBuf,
+        /// 输入文件的字节数
+        bytes: usize,
+    },
+    /// 文件中有多场比赛，各场分别作为单独的输入处理和记录，见 [`process_games`]
+    Games {
+        /// 成功转换的场数
+        converted: usize,
+    },
+}
 
-    let output_path = output_dir.join(output_filename);
+/// 成功转换的文件的信息
+struct ConvertedFile {
+    /// 写出的输出文件，输出到 stdout 或 dry run 时为 `None`
+    output_path: Option<PathBuf>,
+    events: usize,
+    game_length: GameLength,
+    /// 对局开始时间，见 [`game_start_time`]
+    started_at: Option<DateTime<FixedOffset>>,
+}
 
-    // 创建并写入输出文件
-    let mut file = File::create(&output_path)
-        .with_context(|| format!("failed to create output file: {:?}", output_path))?;
+/// `--manifest` 中每个输入文件的记录
+#[derive(Serialize)]
+struct ManifestEntry {
+    input: PathBuf,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    events: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_length: Option<&'static str>,
+    /// RFC 3339 格式的对局开始时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    started_at: Option<String>,
+    /// 内容相同的、先处理的输入文件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// 出错时牌谱还没有读入或解析成功，见 [`LoadError`]
+    #[serde(skip)]
+    load_failed: bool,
+}
 
-    for event in &events {
-        let to_write = serde_json::to_string(event)
-            .context("failed to serialize event to JSON")?;
-        writeln!(file, "{}", to_write)
-            .with_context(|| format!("failed to write to output file: {:?}", output_path))?;
+impl ManifestEntry {
+    fn new(input_path: &Path, result: &Result<FileOutcome>) -> Self {
+        let mut entry = Self {
+            input: input_path.to_path_buf(),
+            status: "",
+            output: None,
+            events: None,
+            game_length: None,
+            started_at: None,
+            duplicate_of: None,
+            error: None,
+            load_failed: false,
+        };
+        match result {
+            Ok(FileOutcome::Converted(converted)) => {
+                entry.status = "converted";
+                entry.output.clone_from(&converted.output_path);
+                entry.events = Some(converted.events);
+                entry.game_length = Some(game_length_name(converted.game_length));
+                entry.started_at = converted.started_at.map(|t| t.to_rfc3339());
+            }
+            Ok(FileOutcome::Filtered) => entry.status = "filtered",
+            Ok(FileOutcome::Skipped) => entry.status = "skipped",
+            Ok(FileOutcome::Duplicate { original, .. }) => {
+                entry.status = "duplicate";
+                entry.duplicate_of = Some(original.clone());
+            }
+            Ok(FileOutcome::Games { .. }) => entry.status = "games",
+            Err(e) if e.downcast_ref::<GamesFailed>().is_some() => entry.status = "games",
+            Err(e) => {
+                entry.status = "error";
+                entry.error = Some(format!("{e:#}"));
+                entry.load_failed = e.downcast_ref::<LoadError>().is_some();
+            }
+        }
+        entry
     }
+}
 
-    log!("successfully converted {:?} -> {:?}",
-         input_path.file_name().unwrap_or_default(),
-         output_path.file_name().unwrap_or_default());
+const fn game_length_name(game_length: GameLength) -> &'static str {
+    match game_length {
+        GameLength::Hanchan => "hanchan",
+        GameLength::Tonpuu => "tonpuusen",
+    }
+}
 
-    Ok(())
+/// 批量转换过程中各文件共用的设置
+#[derive(Default)]
+struct ConvertOptions {
+    recursive: bool,
+    jobs: usize,
+    /// 只检查能否转换，不写出任何文件
+    dry_run: bool,
+    /// 转换后将 mjai 事件与原始牌谱比对
+    verify: bool,
+    /// 转换后检查每局亮出的牌不超过一副牌的张数
+    check_wall: bool,
+    /// 牌谱被截断时只转换其中完整的局
+    best_effort: bool,
+    /// 替换玩家名
+    anonymize: bool,
+    /// 按以盐为密钥的哈希替换玩家名时用的盐
+    anonymize_salt: Option<String>,
+    /// 把牌谱本身的问题（玩家名无法解码、没有对局时间、无法确定房间）作为错误
+    strict: bool,
+    /// 指定时用这么大的读取缓冲区流式解析牌谱，否则先读入全部内容
+    read_buffer_size: Option<NonZeroUsize>,
+    /// 每局输出一个文件
+    split_kyoku: bool,
+    /// 第一个文件转换失败后停止
+    fail_fast: bool,
+    /// 指定 `fail_fast` 且已有文件转换失败
+    aborted: AtomicBool,
+    /// 成功转换这么多个文件后停止
+    limit: Option<usize>,
+    /// `limit` 计的是开始转换的文件数，而不是成功转换的文件数
+    limit_attempts: bool,
+    /// 跳过输出文件比输入文件新的文件
+    incremental: bool,
+    /// 忽略 `incremental`，总是重新转换
+    force: bool,
+    /// 输出到 stdout 时，在每个文件的事件前写入一行 `# <输入文件路径>`，
+    /// 便于下游按文件拆分
+    stdout_marker: bool,
+    /// `--stdout`：只写出一场比赛，多场比赛的文件需要用 `--game-index` 选择其中一场
+    single_game: bool,
+    /// 输出文件名是否保留原扩展名
+    keep_extension: bool,
+    /// 追加在输出文件名后的后缀
+    output_suffix: String,
+    /// 指定 `--output-template` 时按模板生成输出文件名
+    output_template: Option<String>,
+    /// 指定 `--shard` 时将输出文件分散到这么多个子目录中
+    shard: Option<NonZeroUsize>,
+    /// 指定 `--concat` 时所有转换结果写入这一个文件
+    concat: Option<ConcatOutput>,
+    output_format: OutputFormat,
+    /// 在 `start_game` 中写入对局开始时间，见 [`game_start_time`]
+    start_time: bool,
+    game_length: GameLengthFilter,
+    /// 只转换有中途流局或提前结束的比赛
+    only_incomplete: bool,
+    /// 指定 `--game-index` 时只转换文件中的第几场比赛（从 0 开始）
+    game_index: Option<usize>,
+    room: RoomFilter,
+    /// 只转换对局日期（日本时间）在此范围内（含两端）的牌谱
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    /// 指定日期范围时，仍然转换无法确定对局日期的牌谱
+    include_undated: bool,
+    /// 本次运行中已生成的输出路径 -> 对应的输入路径，用于检测文件名冲突
+    claimed_outputs: Mutex<HashMap<PathBuf, PathBuf>>,
+    /// 指定 `--seat-stats` 时汇总的座位统计
+    seat_stats: Option<Mutex<BatchStats>>,
+    player_stats: Option<Mutex<PlayerTendencies>>,
+    yaku_stats: Option<Mutex<YakuStats>>,
+    /// 指定 `--efficiency-export` 时每个输入中的打牌记录，见 [`efficiency_decisions`]
+    efficiency: Option<Mutex<Vec<(PathBuf, Vec<json::Value>)>>>,
+    /// 指定 `--manifest` 时记录的每个文件的处理结果
+    manifest: Option<Mutex<Vec<ManifestEntry>>>,
+    /// 指定 `--checkpoint` 时记录已完成的输入，重新运行时跳过
+    checkpoint: Option<Checkpoint>,
+    /// 缓存转换结果的目录
+    cache_dir: Option<PathBuf>,
+    /// 指定 `--progress` 时显示的进度
+    progress: Option<Progress>,
+    download: DownloadOptions,
+    /// 跳过 URL 列表，不下载其中的牌谱，只处理本地文件
+    offline: bool,
+    /// 指定 `--dedup` 时记录已处理的牌谱：对局内容的哈希 -> 输入路径
+    seen_games: Option<Mutex<HashMap<u64, PathBuf>>>,
+    /// 从 zip 中读出、等待 worker 转换的条目内容，键为 `压缩包路径/条目路径`
+    archive_entries: Mutex<HashMap<PathBuf, Vec<u8>>>,
+    /// `bench` 子命令记录的每个成功转换的文件的耗时和事件数
+    bench_samples: Option<Mutex<Vec<(TimeDelta, usize)>>>,
 }
 
-fn process_directory(input_dir: &Path, output_dir: &Path) -> Result<()> {
-    // 检查输入目录是否存在
-    if !input_dir.exists() {
-        anyhow::bail!("input directory does not exist: {:?}", input_dir);
+impl ConvertOptions {
+    fn in_date_range(&self, date: NaiveDate) -> bool {
+        self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date <= until)
     }
 
-    if !input_dir.is_dir() {
-        anyhow::bail!("input path is not a directory: {:?}", input_dir);
+    /// 在 manifest 和 checkpoint 中记录一个文件的处理结果
+    fn record(&self, input_path: &Path, result: &Result<FileOutcome>) {
+        if let Some(manifest) = &self.manifest {
+            let entry = ManifestEntry::new(input_path, result);
+            manifest.lock().unwrap().push(entry);
+        }
+
+        // dry run 没有写出任何文件，不算完成
+        let done = matches!(
+            result,
+            Ok(FileOutcome::Converted(_)
+                | FileOutcome::Filtered
+                | FileOutcome::Duplicate { .. }
+                | FileOutcome::Games { .. })
+        );
+        let checkpoint = self.checkpoint.as_ref().filter(|_| done && !self.dry_run);
+        if let Err(e) = checkpoint.map_or(Ok(()), |c| c.record(input_path)) {
+            error!("{e:#}");
+        }
     }
+}
 
-    // 创建输出目录（如果不存在）
-    fs::create_dir_all(output_dir)
-        .with_context(|| format!("failed to create output directory: {:?}", output_dir))?;
+/// `--checkpoint` 文件，每行一个已成功转换（或被过滤）的输入的规范化路径。
+///
+/// 每完成一个文件就追加一行，批量转换被中断后用同一个 checkpoint 重新运行时，
+/// 其中的输入会被跳过。与 `--incremental` 不同，它不依赖输出文件的修改时间。
+struct Checkpoint {
+    path: PathBuf,
+    /// 启动时从文件中读取的已完成输入
+    done: HashSet<PathBuf>,
+    file: Mutex<File>,
+}
 
-    log!("processing directory: {:?}", input_dir);
-    log!("output directory: {:?}", output_dir);
+impl Checkpoint {
+    fn open(path: &Path) -> Result<Self> {
+        let done = match fs::read_to_string(path) {
+            Ok(content) => content.lines().filter(|l| !l.is_empty()).map(PathBuf::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read checkpoint file: {path:?}"));
+            }
+        };
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open checkpoint file: {path:?}"))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            done,
+            file: Mutex::new(file),
+        })
+    }
 
-    // 遍历输入目录
-    let entries = fs::read_dir(input_dir)
-        .with_context(|| format!("failed to read input directory: {:?}", input_dir))?;
+    fn contains(&self, input_path: &Path) -> bool {
+        self.done.contains(&Self::key(input_path))
+    }
+
+    fn record(&self, input_path: &Path) -> Result<()> {
+        let line = format!("{}\n", Self::key(input_path).display());
+        // 整行一次写入并立即落盘，进程被杀时最多丢失正在写的这一行
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|()| file.sync_data())
+            .with_context(|| format!("failed to write checkpoint file: {:?}", self.path))
+    }
 
-    let mut processed_count = 0;
-    let mut error_count = 0;
+    /// 使用规范化路径，使 `logs/a.json` 和 `./logs/a.json` 视为同一个输入
+    fn key(input_path: &Path) -> PathBuf {
+        dunce::canonicalize(input_path).unwrap_or_else(|_| input_path.to_path_buf())
+    }
+}
 
-    for entry in entries {
-        match entry {
-            Ok(entry) => {
-                let path = entry.path();
+/// `--concat` 的输出文件，所有转换结果按输入顺序写入其中。
+///
+/// worker 并行转换，每个输入的结果先按输入路径暂存（[`ConcatOutput::add`]），
+/// worker 处理完一个输入后按它的序号提交（[`ConcatOutput::commit`]），
+/// 前面的输入都已提交时才写出，因此各场比赛的事件不会交错，顺序也与输入顺序相同。
+struct ConcatOutput {
+    path: PathBuf,
+    state: Mutex<ConcatState>,
+}
 
-                // 跳过子目录（如果你需要递归处理，可以修改这里）
-                if path.is_dir() {
-                    log!("skipping subdirectory: {:?}", path);
-                    continue;
-                }
+struct ConcatState {
+    writer: BufWriter<File>,
+    /// 已转换、尚未提交的结果，键为输入路径
+    chunks: HashMap<PathBuf, Vec<u8>>,
+    /// 已提交、但前面还有输入未提交的结果，键为输入序号
+    pending: BTreeMap<usize, Vec<u8>>,
+    /// 下一个要写出的输入序号
+    next: usize,
+}
 
-                // 可以根据文件扩展名过滤文件
-                if let Some(ext) = path.extension() {
-                    // 只处理特定扩展名的文件，例如 .log、.txt 等
-                    // 这里可以根据你的需求调整
-                    if ext != "json" && ext != "txt" {
-                        continue;
-                    }
-                }
+impl ConcatOutput {
+    fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create output file: {path:?}"))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            state: Mutex::new(ConcatState {
+                writer: BufWriter::new(file),
+                chunks: HashMap::new(),
+                pending: BTreeMap::new(),
+                next: 0,
+            }),
+        })
+    }
 
-                match process_file(&path, output_dir) {
-                    Ok(_) => {
-                        processed_count += 1;
-                    }
-                    Err(e) => {
-                        error_count += 1;
-                        eprintln!("error processing {:?}: {}", path, e);
-                    }
+    fn add(&self, input_path: &Path, content: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.chunks.entry(input_path.to_path_buf()).or_default().extend(content);
+    }
+
+    /// 提交第 `index` 个输入的结果。被过滤、跳过或失败的输入也要提交，否则后面的结果无法写出
+    fn commit(&self, index: usize, input_path: &Path) -> Result<()> {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let content = state.chunks.remove(input_path).unwrap_or_default();
+        state.pending.insert(index, content);
+        while let Some(content) = state.pending.remove(&state.next) {
+            state.next += 1;
+            state.writer.write_all(&content).with_context(|| self.write_error())?;
+        }
+        Ok(())
+    }
+
+    /// 写出剩下的结果：因中止而未提交的序号之后的，以及不经过 worker 转换的单个文件
+    fn finish(&self) -> Result<()> {
+        let mut guard = self.state.lock().unwrap();
+        let ConcatState { writer, chunks, pending, .. } = &mut *guard;
+        let rest: BTreeMap<_, _> = chunks.drain().collect();
+        for content in mem::take(pending).into_values().chain(rest.into_values()) {
+            writer.write_all(&content).with_context(|| self.write_error())?;
+        }
+        writer.flush().with_context(|| self.write_error())
+    }
+
+    fn write_error(&self) -> String {
+        format!("failed to write output file: {:?}", self.path)
+    }
+}
+
+/// `--progress` 显示的进度，写到 stderr。
+///
+/// stderr 是终端时在同一行中原地更新；否则每隔 [`Progress::INTERVAL`] 输出一行。
+struct Progress {
+    is_terminal: bool,
+    state: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    total: usize,
+    done: usize,
+    errors: usize,
+    last_print: Instant,
+}
+
+impl Progress {
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    fn new() -> Self {
+        Self {
+            is_terminal: io::stderr().is_terminal(),
+            state: Mutex::new(ProgressState {
+                total: 0,
+                done: 0,
+                errors: 0,
+                last_print: Instant::now(),
+            }),
+        }
+    }
+
+    fn set_total(&self, total: usize) {
+        self.state.lock().unwrap().total = total;
+    }
+
+    /// 开始处理 `path`，终端中显示为当前文件
+    fn start(&self, path: &Path) {
+        if self.is_terminal {
+            let state = self.state.lock().unwrap();
+            eprint!("\r\x1b[K[{}/{}] {} errors  {}",
+                    state.done, state.total, state.errors, path.display());
+        }
+    }
+
+    fn finish(&self, failed: bool) {
+        self.advance(1, usize::from(failed));
+    }
+
+    /// 记入 `count` 个已处理完的输入，其中 `errors` 个失败。
+    /// 没有交给 worker 的输入（提交前出错或不再转换）也通过它计入，最终的计数才能达到总数
+    fn advance(&self, count: usize, errors: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.done += count;
+        state.errors += errors;
+        if self.is_terminal {
+            eprint!("\r\x1b[K[{}/{}] {} errors", state.done, state.total, state.errors);
+        } else if state.last_print.elapsed() >= Self::INTERVAL {
+            state.last_print = Instant::now();
+            eprintln!("progress: {}/{} files, {} errors", state.done, state.total, state.errors);
+        }
+    }
+
+    /// 输出一行错误信息，终端中先清除进度行，下次更新时再重新显示
+    fn eprintln(&self, message: &str) {
+        let _state = self.state.lock().unwrap();
+        if self.is_terminal {
+            eprint!("\r\x1b[K");
+        }
+        eprintln!("{message}");
+    }
+
+    fn done(&self) {
+        let state = self.state.lock().unwrap();
+        if self.is_terminal {
+            eprintln!();
+        } else {
+            eprintln!("progress: {}/{} files, {} errors", state.done, state.total, state.errors);
+        }
+    }
+}
+
+/// 基于 `Local::now()` 的计时
+#[derive(Clone, Copy)]
+struct Stopwatch(DateTime<Local>);
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self(Local::now())
+    }
+
+    fn elapsed(self) -> TimeDelta {
+        Local::now() - self.0
+    }
+}
+
+/// 输出目录为 `-` 时表示写到 stdout
+fn is_stdout(output_dir: &Path) -> bool {
+    output_dir == Path::new("-")
+}
+
+fn process_file(
+    input_path: &Path,
+    output_dir: &Path,
+    opts: &ConvertOptions,
+) -> Result<FileOutcome> {
+    process_entry(input_path, output_dir, opts, None)
+}
+
+/// 转换一个输入。`bundle` 为 [`process_games`] 从多场比赛的文件中拆出一场时所属的文件。
+fn process_entry(
+    input_path: &Path,
+    output_dir: &Path,
+    opts: &ConvertOptions,
+    bundle: Option<&Path>,
+) -> Result<FileOutcome> {
+    log!("processing file: {:?}", input_path);
+    // 先取出，提前返回时也不会留在内存中
+    let archived = opts.archive_entries.lock().unwrap().remove(input_path);
+
+    // 输出文件名不依赖牌谱内容时，在读取文件之前就检查输出是否已是最新
+    let output = match &opts.output_template {
+        Some(_) => None,
+        None => {
+            let file_name = output_file_name(input_path, opts);
+            match resolve_output(input_path, output_dir, file_name, opts, bundle)? {
+                Some(output) => Some(output),
+                None => return Ok(FileOutcome::Skipped),
+            }
+        }
+    };
+
+    if !opts.force && opts.checkpoint.as_ref().is_some_and(|c| c.contains(input_path)) {
+        log!("skipping {:?}: already converted according to checkpoint",
+             input_path.file_name().unwrap_or_default());
+        return Ok(FileOutcome::Skipped);
+    }
+
+    // 先解析为通用的 JSON，在完整解析 RawLog 之前检查牌谱结构和类型
+    let loaded = load_log(input_path, archived, opts)
+        .map_err(|e| anyhow::Error::new(LoadError(e)))?;
+    let LoadedLog { mut raw, bytes, cache_hash } = match loaded {
+        Loaded::Log(loaded) => loaded,
+        Loaded::Games(games) => return process_games(input_path, output_dir, games, opts),
+    };
+    if bundle.is_none() && opts.game_index.is_some_and(|index| index > 0) {
+        log!("skipping {:?}: the file contains a single game (--game-index)",
+             input_path.file_name().unwrap_or_default());
+        return Ok(FileOutcome::Filtered);
+    }
+    ensure!(!is_sanma(&raw), SANMA_UNSUPPORTED);
+    for warning in normalize_names(&mut raw) {
+        ensure!(!opts.strict, "{warning} (--strict)");
+        warning!("{input_path:?}: {warning}");
+    }
+    if opts.strict {
+        check_strict(&raw, input_path)?;
+    }
+    let cache_hash = if opts.anonymize {
+        anonymize_names(&mut raw, opts.anonymize_salt.as_deref());
+        // 转换所用的名字与牌谱中的不同，缓存键也要随之不同
+        let mut hasher = ContentHasher::default();
+        hasher.write_u64(cache_hash);
+        hasher.write(raw["name"].to_string().as_bytes());
+        hasher.finish()
+    } else {
+        cache_hash
+    };
+
+    if !opts.room.matches(&raw) {
+        log!("skipping {:?}: room does not match --room",
+             input_path.file_name().unwrap_or_default());
+        return Ok(FileOutcome::Filtered);
+    }
+
+    if opts.since.is_some() || opts.until.is_some() {
+        // 文件的修改时间不代表对局时间，这里只使用牌谱本身的时间
+        match logged_start_time(&raw, input_path) {
+            Some(started_at) if !opts.in_date_range(started_at.date_naive()) => {
+                log!("skipping {:?}: game date is outside --since/--until",
+                     input_path.file_name().unwrap_or_default());
+                return Ok(FileOutcome::Filtered);
+            }
+            Some(_) => (),
+            None if opts.include_undated => (),
+            None => {
+                warning!("skipping {input_path:?}: no game timestamp in log or file name \
+                          (use --include-undated to convert it)");
+                return Ok(FileOutcome::Filtered);
+            }
+        }
+    }
+
+    if let Some(seen_games) = &opts.seen_games {
+        let hash = game_content_hash(&raw)?;
+        let mut seen_games = seen_games.lock().unwrap();
+        if let Some(original) = seen_games.get(&hash) {
+            log!("skipping {:?}: duplicate of {:?}", input_path, original);
+            return Ok(FileOutcome::Duplicate {
+                original: original.clone(),
+                bytes,
+            });
+        }
+        seen_games.insert(hash, input_path.to_path_buf());
+    }
+
+    // 使用 --output-template 时，读取牌谱之后才能确定输出文件名
+    let output = match output {
+        Some(output) => output,
+        None => {
+            let template = opts.output_template.as_deref().unwrap_or_default();
+            let file_name = render_output_template(template, |name| {
+                template_value(name, input_path, &raw)
+            })?;
+            match resolve_output(input_path, output_dir, file_name, opts, bundle)? {
+                Some(output) => output,
+                None => return Ok(FileOutcome::Skipped),
+            }
+        }
+    };
+
+    let filtered_out = |game_length| {
+        if !opts.game_length.matches(game_length) {
+            log!("skipping {:?}: game length does not match --game-length",
+                 input_path.file_name().unwrap_or_default());
+            return true;
+        }
+        if opts.only_incomplete && !is_incomplete_game(&raw, game_length) {
+            log!("skipping {:?}: no abortive draw and not ended early (--only-incomplete)",
+                 input_path.file_name().unwrap_or_default());
+            return true;
+        }
+        false
+    };
+
+    // 指定 --cache-dir 时，内容相同的牌谱直接读取上次的转换结果
+    let cache_path = opts.cache_dir.as_ref().map(|dir| dir.join(cache_file_name(cache_hash)));
+    let stopwatch = Stopwatch::start();
+    let (game_length, events) = match cache_path.as_deref().and_then(read_cache) {
+        Some(cached) => {
+            let game_length = if cached.tonpuu { GameLength::Tonpuu } else { GameLength::Hanchan };
+            if filtered_out(game_length) {
+                return Ok(FileOutcome::Filtered);
+            }
+            verbose!("loaded {} events for {:?} from cache in {} ms (converting took {} ms)",
+                     cached.events.len(),
+                     input_path.file_name().unwrap_or_default(),
+                     stopwatch.elapsed().num_milliseconds(),
+                     cached.convert_ms);
+            (game_length, cached.events.into_owned())
+        }
+        None => {
+            // 解析 RawLog（原来是从 json 解析的）
+            let raw_log = RawLog::deserialize(&raw).with_context(|| {
+                format!("failed to parse tenhou.net/6 log from file: {:?}", input_path)
+            })?;
+
+            // convert from RawLog to Log
+            let log = Log::try_from(raw_log).context("invalid log")?;
+            if filtered_out(log.game_length) {
+                return Ok(FileOutcome::Filtered);
+            }
+
+            // convert from tenhou::Log to Vec<mjai::Event>
+            log!("converting {:?} to mjai events...", input_path.file_name().unwrap_or_default());
+            let events = convert_raw_log(&log)
+                .with_context(|| format!("failed to convert {:?} into mjai format", input_path))?;
+            let convert_ms = stopwatch.elapsed().num_milliseconds();
+            verbose!("converted {:?} into {} events in {} ms",
+                     input_path.file_name().unwrap_or_default(),
+                     events.len(),
+                     convert_ms);
+
+            if let Some(cache_path) = cache_path.as_deref().filter(|_| !opts.dry_run) {
+                let entry = CacheEntry {
+                    tonpuu: matches!(log.game_length, GameLength::Tonpuu),
+                    convert_ms,
+                    events: Cow::Borrowed(&events),
+                };
+                // 缓存写入失败不影响转换本身
+                if let Err(e) = write_cache(cache_path, &entry) {
+                    warning!("{e:#}");
                 }
             }
+            (log.game_length, events)
+        }
+    };
+
+    if opts.verify {
+        let mismatches = verify_events(&raw, &events)?;
+        if !mismatches.is_empty() {
+            let details: String = mismatches.iter().map(|m| format!("\n  {m}")).collect();
+            bail!("verification failed for {:?}:{details}", input_path);
+        }
+    }
+    if opts.check_wall {
+        let mismatches = check_wall(&events)
+            .with_context(|| format!("wall check failed for {input_path:?}"))?;
+        if !mismatches.is_empty() {
+            let details: String = mismatches.iter().map(|m| format!("\n  {m}")).collect();
+            bail!("wall check failed for {input_path:?}:{details}");
+        }
+    }
+
+    if let Some(seat_stats) = &opts.seat_stats {
+        seat_stats.lock().unwrap().add_game(&events)?;
+    }
+    if let Some(player_stats) = &opts.player_stats {
+        player_stats.lock().unwrap().add_game(&events)?;
+    }
+    if let Some(yaku_stats) = &opts.yaku_stats {
+        yaku_stats.lock().unwrap().add_game(&raw);
+    }
+    if let Some(efficiency) = &opts.efficiency {
+        let decisions = efficiency_decisions(&events)?;
+        efficiency.lock().unwrap().push((input_path.to_path_buf(), decisions));
+    }
+
+    // 没有文件中的时间时会退回到修改时间并给出警告，只在需要时获取
+    let started_at = if opts.start_time || opts.manifest.is_some() {
+        game_start_time(&raw, input_path)
+    } else {
+        None
+    };
+
+    if opts.dry_run {
+        log!("successfully converted {:?} ({} events, dry run)",
+             input_path.file_name().unwrap_or_default(),
+             events.len());
+        return Ok(FileOutcome::Converted(ConvertedFile {
+            output_path: None,
+            events: events.len(),
+            game_length,
+            started_at,
+        }));
+    }
+
+    let output_path = match output {
+        ReportOutput::File(output_path) if opts.split_kyoku => {
+            let kyoku_count =
+                write_split_kyoku(&output_path, input_path, &events, started_at, opts)?;
+            log!("successfully converted {:?} -> {} kyoku files",
+                 input_path.file_name().unwrap_or_default(),
+                 kyoku_count);
+            None
+        }
+        ReportOutput::File(output_path) => {
+            write_output_file(&output_path, &events, started_at, opts)?;
+            log!("successfully converted {:?} -> {:?}",
+                 input_path.file_name().unwrap_or_default(),
+                 output_path.file_name().unwrap_or_default());
+            Some(output_path)
+        }
+        ReportOutput::Stdout => {
+            // 整个文件写完前一直持有 stdout 的锁，多线程时各文件的事件不会交错
+            let mut writer = BufWriter::new(io::stdout().lock());
+            if opts.stdout_marker {
+                writeln!(writer, "# {}", input_path.display())
+                    .context("failed to write to stdout")?;
+            }
+            write_output(&mut writer, &events, started_at, opts)
+                .context("failed to write to stdout")?;
+
+            log!("successfully converted {:?} -> <stdout>",
+                 input_path.file_name().unwrap_or_default());
+            None
+        }
+        ReportOutput::Concat => {
+            let concat = opts.concat.as_ref().expect("ReportOutput::Concat without --concat");
+            let mut buffer = Vec::new();
+            write_output(&mut buffer, &events, started_at, opts)?;
+            // 多场比赛的文件中的各场按序号依次追加，整个文件轮到时一起写出
+            concat.add(bundle.unwrap_or(input_path), buffer);
+            log!("successfully converted {:?} -> {:?}",
+                 input_path.file_name().unwrap_or_default(),
+                 concat.path.file_name().unwrap_or_default());
+            Some(concat.path.clone())
+        }
+    };
+
+    Ok(FileOutcome::Converted(ConvertedFile {
+        output_path,
+        events: events.len(),
+        game_length,
+        started_at,
+    }))
+}
+
+/// 转换一个文件中的多场比赛。
+///
+/// 每场比赛以 `{文件名}_game{序号}.json`（序号从 0 开始）为输入路径放入 `archive_entries`，
+/// 再按单个文件处理，输出文件名、过滤、检查、manifest 和 checkpoint 都以场为单位。
+/// 指定 `--game-index` 时只处理该场。有任何一场失败时整个文件算作失败。
+fn process_games(
+    input_path: &Path,
+    output_dir: &Path,
+    games: Vec<json::Value>,
+    opts: &ConvertOptions,
+) -> Result<FileOutcome> {
+    verbose!("{:?} contains {} games", input_path.file_name().unwrap_or_default(), games.len());
+    let total = games.len();
+    if opts.game_index.is_some_and(|index| index >= total) {
+        log!("skipping {:?}: the file contains only {total} games (--game-index)",
+             input_path.file_name().unwrap_or_default());
+        return Ok(FileOutcome::Filtered);
+    }
+    // 各场的事件依次写到 stdout 时无法区分
+    ensure!(!opts.single_game || opts.game_index.is_some(),
+            "{input_path:?} contains {total} games, --stdout writes a single game, \
+             select one with --game-index");
+
+    let stem = input_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let mut converted = 0;
+    let mut failures = vec![];
+    for (index, game) in games.into_iter().enumerate() {
+        if opts.game_index.is_some_and(|selected| selected != index) {
+            continue;
+        }
+        let game_path = input_path.with_file_name(format!("{stem}_game{index}.json"));
+        let body = json::to_vec(&game).context("failed to serialize game")?;
+        opts.archive_entries.lock().unwrap().insert(game_path.clone(), body);
+        let result = process_entry(&game_path, output_dir, opts, Some(input_path));
+        opts.record(&game_path, &result);
+        match result {
+            Ok(FileOutcome::Converted(_)) => converted += 1,
+            Ok(_) => (),
             Err(e) => {
-                error_count += 1;
-                eprintln!("error reading directory entry: {}", e);
+                error!("error processing {game_path:?}: {e:#}");
+                failures.push(ConvertFailure { path: game_path, message: format!("{e:#}") });
+            }
+        }
+    }
+    if !failures.is_empty() {
+        return Err(GamesFailed { failures, total }.into());
+    }
+    Ok(FileOutcome::Games { converted })
+}
+
+/// 对局内容的哈希，用于 `--dedup`。
+///
+/// 只对 `log`、`name` 和 `rule` 重新序列化后的 JSON 计算，与文件名、缩进和空白以及
+/// `ref` 等元数据无关，只是重新排版或另存的同一个牌谱会得到相同的哈希。
+fn game_content_hash(raw: &json::Value) -> Result<u64> {
+    let mut hasher = ContentHasher::default();
+    for key in ["log", "name", "rule"] {
+        let canonical = json::to_vec(&raw[key]).context("failed to serialize log for hashing")?;
+        hasher.write(&canonical);
+    }
+    Ok(hasher.finish())
+}
+
+/// `--cache-dir` 中缓存的一个牌谱的转换结果
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<'a> {
+    /// 是否为东风战
+    tonpuu: bool,
+    /// 原本转换所用的毫秒数，用于 `--verbose` 中报告节省的时间
+    convert_ms: i64,
+    events: Cow<'a, [Event]>,
+}
+
+/// 缓存文件名由牌谱内容和程序版本的哈希决定，牌谱内容或转换程序变化后旧缓存自然失效
+fn cache_file_name(cache_hash: u64) -> String {
+    format!("{cache_hash:016x}.msgpack")
+}
+
+/// 计算缓存键的 hasher，已写入本程序的版本号，之后再写入牌谱内容
+fn cache_hasher() -> ContentHasher {
+    let mut hasher = ContentHasher::default();
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher
+}
+
+/// 读取缓存，缓存不存在或无法解析时返回 `None`，退回到完整的转换
+fn read_cache(cache_path: &Path) -> Option<CacheEntry<'static>> {
+    let bytes = match fs::read(cache_path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warning!("failed to read cache file {cache_path:?}: {e}");
+            return None;
+        }
+    };
+    rmp_serde::from_slice(&bytes)
+        .inspect_err(|e| warning!("ignoring invalid cache file {cache_path:?}: {e}"))
+        .ok()
+}
+
+/// 先写入临时文件再重命名，其他 worker 不会读到写了一半的缓存
+fn write_cache(cache_path: &Path, entry: &CacheEntry<'_>) -> Result<()> {
+    let bytes = rmp_serde::to_vec_named(entry).context("failed to serialize cache entry")?;
+    let tmp_path = cache_path.with_extension(format!("{:?}.tmp", thread::current().id()));
+    fs::write(&tmp_path, bytes)
+        .and_then(|()| fs::rename(&tmp_path, cache_path))
+        .with_context(|| format!("failed to write cache file: {cache_path:?}"))
+}
+
+/// 获取对局开始时间（精确到秒）。
+///
+/// 优先使用 [`logged_start_time`]，没有时退回到输入文件的修改时间并给出警告。
+fn game_start_time(raw: &json::Value, input_path: &Path) -> Option<DateTime<FixedOffset>> {
+    let started_at = logged_start_time(raw, input_path);
+    if started_at.is_some() {
+        return started_at;
+    }
+
+    let modified = fs::metadata(input_path).and_then(|m| m.modified()).ok()?;
+    warning!("{input_path:?}: no game timestamp in log or file name, using file mtime");
+    Some(DateTime::<Local>::from(modified).trunc_subsecs(0).fixed_offset())
+}
+
+/// 从牌谱本身获取对局开始时间（日本时间，精确到小时）。
+///
+/// 优先使用牌谱中 `ref` 字段的牌谱 ID（如 `2019070419gm-00a9-0000-557e4086`，
+/// 前 10 位为日本时间的年月日时），其次使用同样以牌谱 ID 开头的文件名。
+fn logged_start_time(raw: &json::Value, input_path: &Path) -> Option<DateTime<FixedOffset>> {
+    let jst = FixedOffset::east_opt(9 * 3600)?;
+    let from_log_id = |log_id: &str| {
+        let hour = log_id.get(..10)?;
+        NaiveDateTime::parse_from_str(&format!("{hour}00"), "%Y%m%d%H%M")
+            .ok()?
+            .and_local_timezone(jst)
+            .single()
+    };
+
+    let file_name = input_path.file_name().unwrap_or_default().to_string_lossy();
+    raw["ref"]
+        .as_str()
+        .and_then(from_log_id)
+        .or_else(|| from_log_id(&file_name))
+}
+
+/// 输出文件已存在且修改时间不早于输入文件时返回 `true`
+fn is_up_to_date(input_path: &Path, output_path: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(input_path), modified(output_path)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+/// 按 `opts.output_format` 写出转换结果，指定 `--start-time` 时在 `start_game` 中加入
+/// `started_at`
+fn write_output<W: Write>(
+    writer: &mut W,
+    events: &[Event],
+    started_at: Option<DateTime<FixedOffset>>,
+    opts: &ConvertOptions,
+) -> Result<()> {
+    let started_at = started_at.filter(|_| opts.start_time);
+    match (opts.output_format, started_at) {
+        (OutputFormat::Hands, _) => write_final_hands(writer, events),
+        (OutputFormat::Ndjson, None) => write_events(writer, events),
+        (OutputFormat::Array, None) => write_events_array(writer, events),
+        (format, Some(started_at)) => {
+            // convlog 的 Event 没有多余的字段，先转为 JSON 再加入
+            let mut values = events
+                .iter()
+                .map(json::to_value)
+                .collect::<Result<Vec<_>, _>>()
+                .context("failed to serialize events")?;
+            if let Some(start_game) = values.first_mut().filter(|e| e["type"] == "start_game") {
+                start_game["started_at"] = started_at.to_rfc3339().into();
             }
+            if format == OutputFormat::Array {
+                write_events_array(writer, &values)
+            } else {
+                write_events(writer, &values)
+            }
+        }
+    }
+}
+
+/// 创建并写入输出文件
+fn write_output_file(
+    output_path: &Path,
+    events: &[Event],
+    started_at: Option<DateTime<FixedOffset>>,
+    opts: &ConvertOptions,
+) -> Result<()> {
+    let file = File::create(output_path)
+        .with_context(|| format!("failed to create output file: {:?}", output_path))?;
+    let mut writer = BufWriter::new(file);
+    write_output(&mut writer, events, started_at, opts)
+        .with_context(|| format!("failed to write to output file: {:?}", output_path))
+}
+
+/// `--split-kyoku`：将每局写成单独的文件，返回写出的文件数。
+///
+/// 每个文件都以原来的 `start_game` 开头、`end_game` 结尾，可以单独作为 mjai 牌谱读取。
+/// 文件名为在 `output_path` 的后缀前加上 [`kyoku_tag`]，如 `game_E1.json`。
+fn write_split_kyoku(
+    output_path: &Path,
+    input_path: &Path,
+    events: &[Event],
+    started_at: Option<DateTime<FixedOffset>>,
+    opts: &ConvertOptions,
+) -> Result<usize> {
+    let Some((start_game, rest)) = events.split_first() else {
+        bail!("no events to split into kyoku");
+    };
+    let file_name = output_path.file_name().unwrap_or_default().to_string_lossy();
+    let stem = file_name.strip_suffix(opts.output_suffix.as_str()).unwrap_or(&file_name);
+
+    let starts: Vec<_> = rest
+        .iter()
+        .enumerate()
+        .filter(|(_, event)| matches!(event, Event::StartKyoku { .. }))
+        .map(|(i, _)| i)
+        .collect();
+    for (n, &start) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).copied().unwrap_or(rest.len());
+        let mut kyoku = &rest[start..end];
+        // 最后一局之后是原来的 end_game
+        if matches!(kyoku.last(), Some(Event::EndGame)) {
+            kyoku = &kyoku[..kyoku.len() - 1];
         }
+
+        let tag = kyoku_tag(&kyoku[0])?;
+        let kyoku_path = output_path.with_file_name(format!("{stem}_{tag}{}", opts.output_suffix));
+        claim_output_path(&kyoku_path, input_path, opts)?;
+
+        let kyoku_events: Vec<_> = iter::once(start_game.clone())
+            .chain(kyoku.iter().cloned())
+            .chain(iter::once(Event::EndGame))
+            .collect();
+        write_output_file(&kyoku_path, &kyoku_events, started_at, opts)?;
     }
+    Ok(starts.len())
+}
 
-    log!("processing completed: {} files processed, {} errors",
-         processed_count, error_count);
+/// 局的名称，如 `E1`（东一局）、`S3_honba2`（南三局二本场）。
+///
+/// 连庄和流局时本场数增加，局名不变，因此同一局的连庄会依次得到
+/// `E1`、`E1_honba1`、`E1_honba2` ……
+fn kyoku_tag(start_kyoku: &Event) -> Result<String> {
+    let value = json::to_value(start_kyoku).context("failed to serialize start_kyoku")?;
+    let bakaze = value["bakaze"].as_str().context("start_kyoku without bakaze")?;
+    let kyoku = value["kyoku"].as_u64().context("start_kyoku without kyoku")?;
+    let honba = value["honba"].as_u64().unwrap_or_default();
+    Ok(if honba == 0 {
+        format!("{bakaze}{kyoku}")
+    } else {
+        format!("{bakaze}{kyoku}_honba{honba}")
+    })
+}
 
-    if error_count > 0 {
-        anyhow::bail!("some files failed to process ({} errors)", error_count);
+/// 确定输出位置并登记输出路径。指定 `--incremental` 且输出文件已是最新时返回 `None`。
+///
+/// 从多场比赛的文件中拆出的一场（`bundle` 为所属的文件）没有自己的文件，与所属文件的修改时间比较。
+fn resolve_output(
+    input_path: &Path,
+    output_dir: &Path,
+    file_name: String,
+    opts: &ConvertOptions,
+    bundle: Option<&Path>,
+) -> Result<Option<ReportOutput>> {
+    if opts.concat.is_some() {
+        return Ok(Some(ReportOutput::Concat));
+    }
+    if is_stdout(output_dir) {
+        return Ok(Some(ReportOutput::Stdout));
     }
 
-    Ok(())
+    let output_path = match opts.shard {
+        Some(shards) => output_dir.join(shard_dir(&file_name, shards)).join(file_name),
+        None => output_dir.join(file_name),
+    };
+    claim_output_path(&output_path, input_path, opts)?;
+    let source = bundle.unwrap_or(input_path);
+    if opts.incremental && !opts.force && is_up_to_date(source, &output_path) {
+        log!("skipping {:?}: output is up to date",
+             input_path.file_name().unwrap_or_default());
+        return Ok(None);
+    }
+
+    // 输出模板中可以含有子目录
+    if let Some(parent) = output_path.parent().filter(|_| !opts.dry_run) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create output directory: {parent:?}"))?;
+    }
+    Ok(Some(ReportOutput::File(output_path)))
 }
 
-// 主函数示例
-fn main() -> Result<()> {
-    // 使用示例：从命令行参数获取输入输出目录
-    let args: Vec<String> = std::env::args().collect();
+/// `--shard`：按输出文件名的哈希选择子目录，名称为定长的十六进制数，如 256 个时为 `00`~`ff`
+fn shard_dir(file_name: &str, shards: NonZeroUsize) -> String {
+    let mut hasher = ContentHasher::default();
+    hasher.write(file_name.as_bytes());
+    let shard = hasher.finish() % shards.get() as u64;
+    let width = format!("{:x}", shards.get() - 1).len();
+    format!("{shard:0width$x}")
+}
+
+/// `--output-template` 中可用的占位符
+const TEMPLATE_PLACEHOLDERS: [&str; 5] = ["stem", "game_id", "date", "length", "players"];
+
+/// 将 `template` 中的 `{名称}` 替换为 `value(名称)`，替换进来的值中不能用于文件名的字符
+/// 会被替换为 `_`。`value` 对未知的占位符返回 `None`。
+fn render_output_template(
+    template: &str,
+    value: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some((literal, tail)) = rest.split_once('{') {
+        rendered.push_str(literal);
+        let Some((name, tail)) = tail.split_once('}') else {
+            bail!("unterminated placeholder in output template: {template:?}");
+        };
+        let Some(value) = value(name) else {
+            bail!(
+                "unknown placeholder {{{name}}} in output template, expected one of: {}",
+                TEMPLATE_PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+            );
+        };
+        rendered.extend(value.chars().map(|c| {
+            let illegal = c.is_control()
+                || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|');
+            if illegal { '_' } else { c }
+        }));
+        rest = tail;
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// `--output-template` 中占位符 `name` 对应的值
+fn template_value(name: &str, input_path: &Path, raw: &json::Value) -> Option<String> {
+    let mut input_name = PathBuf::from(input_path.file_name().unwrap_or_default());
+    if is_gzip_extension(&input_name) {
+        input_name.set_extension("");
+    }
+    let stem = input_name.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+
+    let value = match name {
+        "game_id" => raw["ref"]
+            .as_str()
+            .or_else(|| tenhou_log_id(&stem))
+            .unwrap_or("unknown")
+            .to_owned(),
+        "date" => logged_start_time(raw, input_path)
+            .map_or_else(|| "undated".to_owned(), |t| t.format("%Y-%m-%d").to_string()),
+        // 与 convlog 相同，根据规则名（如 `鳳南喰赤`）判断东风战和半庄战
+        "length" => {
+            let disp = raw["rule"]["disp"].as_str().unwrap_or_default();
+            let length = if disp.contains('東') { "tonpuusen" } else { "hanchan" };
+            length.to_owned()
+        }
+        "players" => raw["name"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|name| name.as_str().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("_"),
+        "stem" => stem,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// 根据输入文件名生成输出文件名。
+///
+/// 默认去掉原扩展名再加上 `opts.output_suffix`，即 `game.txt` -> `game.json`；
+/// 开启 `opts.keep_extension` 时保留原扩展名，即 `game.txt` -> `game.txt.json`。
+/// `.gz` 扩展名总是会先被去掉。
+fn output_file_name(input_path: &Path, opts: &ConvertOptions) -> String {
+    // 对于 foo.json.gz，先去掉 .gz 再取文件名
+    let mut input_name = PathBuf::from(input_path.file_name().unwrap_or_default());
+    if is_gzip_extension(&input_name) {
+        input_name.set_extension("");
+    }
+
+    let base = if opts.keep_extension {
+        input_name.as_os_str()
+    } else {
+        input_name.file_stem().unwrap_or_default()
+    };
+    base.to_string_lossy().into_owned() + &opts.output_suffix
+}
 
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input_directory> <output_directory>", args[0]);
-        std::process::exit(1);
+/// 记录本次运行中 `output_path` 由 `input_path` 生成。
+/// 若之前已有其他输入映射到同一输出路径则报错，避免后者静默覆盖前者。
+fn claim_output_path(output_path: &Path, input_path: &Path, opts: &ConvertOptions) -> Result<()> {
+    let mut claimed = opts.claimed_outputs.lock().unwrap();
+    if let Some(previous) = claimed.get(output_path) {
+        bail!(
+            "output file {output_path:?} would collide with the output of {previous:?} \
+            (try --keep-extension or --output-suffix)"
+        );
     }
+    claimed.insert(output_path.to_path_buf(), input_path.to_path_buf());
+    Ok(())
+}
+
+/// [`load_log`] 的结果
+enum Loaded {
+    Log(LoadedLog),
+    /// 文件中有多场比赛，见 [`split_games`]
+    Games(Vec<json::Value>),
+}
 
-    let input_dir = Path::new(&args[1]);
-    let output_dir = Path::new(&args[2]);
+/// 读入并解析后的牌谱
+struct LoadedLog {
+    raw: json::Value,
+    /// 牌谱内容的字节数（压缩文件为解压后的）
+    bytes: usize,
+    /// 牌谱内容的缓存键，见 [`cache_hasher`]
+    cache_hash: u64,
+}
 
-    process_directory(input_dir, output_dir)
+/// 读取并解析牌谱。
+///
+/// 默认用 [`read_log_file`] 读入全部内容再解析：对常见大小的牌谱，这比流式解析快，
+/// 而 serde_json 流式解析出的 `Value` 树仍在内存中，并不能限制内存占用。
+/// 指定 `opts.read_buffer_size` 时，普通的 JSON 文件改用这么大的缓冲区流式解析；
+/// 压缩文件、zip 中的条目、`--best-effort`（需要完整内容来修复截断的牌谱），
+/// 以及流式解析失败时（以便给出更详细的错误信息）仍退回到读入全部内容。
+/// 按单个牌谱解析失败时，再检查是否为包含多场比赛的文件。
+fn load_log(input_path: &Path, archived: Option<Vec<u8>>, opts: &ConvertOptions) -> Result<Loaded> {
+    let streaming = archived.is_none() && !opts.best_effort && !is_gzip_extension(input_path);
+    if let Some(capacity) = opts.read_buffer_size.filter(|_| streaming) {
+        let file = File::open(input_path)
+            .with_context(|| format!("failed to open file: {input_path:?}"))?;
+        let mut reader = BufReader::with_capacity(capacity.get(), file);
+        let head = reader
+            .fill_buf()
+            .with_context(|| format!("failed to read file: {input_path:?}"))?;
+        if !head.starts_with(&GZIP_MAGIC) {
+            let mut reader = HashingReader { inner: reader, hasher: cache_hasher(), bytes: 0 };
+            if let Ok(raw) = parse_tenhou_reader(&mut reader) {
+                let cache_hash = reader.hasher.finish();
+                return Ok(Loaded::Log(LoadedLog { raw, bytes: reader.bytes, cache_hash }));
+            }
+        }
+    }
+
+    let body = read_log_file(input_path, archived)?;
+    let parsed = if opts.best_effort {
+        parse_tenhou_json_lenient(&body).map(|(raw, warning)| {
+            if let Some(warning) = warning {
+                warning!("{input_path:?}: {warning}");
+            }
+            raw
+        })
+    } else {
+        parse_tenhou_json(&body)
+    };
+    let raw = match parsed {
+        Ok(raw) => raw,
+        Err(e) => return split_games(&body).map(Loaded::Games).ok_or(e),
+    };
+    let mut hasher = cache_hasher();
+    hasher.write(body.as_bytes());
+    Ok(Loaded::Log(LoadedLog { raw, bytes: body.len(), cache_hash: hasher.finish() }))
+}
+
+/// [`load_log`] 的错误：牌谱无法读取，或者不是可以解析的 tenhou.net/6 牌谱。
+///
+/// 显示的内容和错误链与原来的错误相同，只用于让 `validate` 区分无法解析和解析后无法转换的牌谱。
+#[derive(Debug)]
+struct LoadError(anyhow::Error);
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// [`process_games`] 的错误：文件中有几场比赛转换失败。
+///
+/// 各场的错误已经输出，并以场为单位记录在 manifest 中。统计时计入的是失败的场数，
+/// 整个文件不再另算一个错误。
+#[derive(Debug)]
+struct GamesFailed {
+    failures: Vec<ConvertFailure>,
+    total: usize,
+}
+
+impl fmt::Display for GamesFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} games in the file failed to convert", self.failures.len(), self.total)
+    }
+}
+
+impl std::error::Error for GamesFailed {}
+
+/// 在读取的同时计算内容的哈希和字节数
+struct HashingReader<R> {
+    inner: R,
+    hasher: ContentHasher,
+    bytes: usize,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+        self.bytes += n;
+        Ok(n)
+    }
+}
+
+/// 读取日志文件内容。扩展名为 `.gz` 或以 gzip 魔数（`1f 8b`）开头的文件会先解压。
+///
+/// `archived` 为从 zip 中读出的内容，此时不再读取 `input_path`。
+fn read_log_file(input_path: &Path, archived: Option<Vec<u8>>) -> Result<String> {
+    let raw = match archived {
+        Some(raw) => raw,
+        None => {
+            let mut file = File::open(input_path)
+                .with_context(|| format!("failed to open file: {:?}", input_path))?;
+            let mut raw = Vec::new();
+            file.read_to_end(&mut raw)
+                .with_context(|| format!("failed to read file: {:?}", input_path))?;
+            raw
+        }
+    };
+
+    if !is_gzip_extension(input_path) && !raw.starts_with(&GZIP_MAGIC) {
+        return String::from_utf8(raw)
+            .with_context(|| format!("failed to read file: {:?}", input_path));
+    }
+
+    let mut body = String::new();
+    MultiGzDecoder::new(raw.as_slice())
+        .read_to_string(&mut body)
+        .with_context(|| format!("failed to decompress gzip file: {:?}", input_path))?;
+    Ok(body)
+}
+
+/// 没有扩展名或扩展名为 `.json`、`.txt`、`.gz` 的文件视为牌谱
+fn is_gzip_extension(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// 输出批量转换的汇总信息，有文件处理失败时返回错误
+fn report_stats(stats: &ConvertStats) -> Result<()> {
+    let errors = if stats.errors > 0 {
+        paint(stats.errors, Color::Red)
+    } else {
+        stats.errors.to_string()
+    };
+    log!("processing completed: {} files processed, {errors} errors",
+         paint(stats.processed, Color::Green));
+    if stats.filtered > 0 {
+        log!("{} files filtered out", paint(stats.filtered, Color::Yellow));
+    }
+    if stats.skipped > 0 {
+        log!("{} files skipped (output up to date)", paint(stats.skipped, Color::Yellow));
+    }
+    if stats.duplicates > 0 {
+        log!("{} duplicate files skipped ({} bytes)",
+             paint(stats.duplicates, Color::Yellow),
+             stats.duplicate_bytes);
+    }
+
+    if let Some(path) = &stats.first_failure {
+        anyhow::bail!("stopped at the first error (--fail-fast): {path:?} failed to process");
+    }
+    if stats.errors > 0 {
+        anyhow::bail!("some files failed to process ({} errors)", stats.errors);
+    }
+
+    Ok(())
+}
+
+/// 转换目录中的所有牌谱，返回各类结果的计数。只有目录本身无法读取时才返回错误。
+fn process_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    opts: &ConvertOptions,
+) -> Result<ConvertStats> {
+    // 检查输入目录是否存在
+    if !input_dir.exists() {
+        anyhow::bail!("input directory does not exist: {:?}", input_dir);
+    }
+
+    if !input_dir.is_dir() {
+        anyhow::bail!("input path is not a directory: {:?}", input_dir);
+    }
+
+    create_output_dir(output_dir, opts)?;
+
+    log!("processing directory: {:?}", input_dir);
+    log!("output directory: {:?}", output_dir);
+    log!("using {} worker threads", opts.jobs);
+
+    let stats = run_workers(opts, None, |on_file| {
+        let mut walk_error_count = 0;
+        let mut visited_dirs = HashSet::new();
+        visit_directory(
+            input_dir,
+            output_dir,
+            opts,
+            &mut visited_dirs,
+            &mut walk_error_count,
+            on_file,
+        );
+        walk_error_count
+    });
+
+    Ok(stats)
+}
+
+/// 转换 `list_path` 中逐行列出的文件，`list_path` 为 `-` 时从 stdin 读取。
+/// 空行和以 `#` 开头的行会被忽略。
+fn process_file_list(
+    list_path: &Path,
+    output_dir: &Path,
+    opts: &ConvertOptions,
+) -> Result<ConvertStats> {
+    let reader: Box<dyn BufRead> = if list_path == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        let file = File::open(list_path)
+            .with_context(|| format!("failed to open file list: {:?}", list_path))?;
+        Box::new(BufReader::new(file))
+    };
+
+    create_output_dir(output_dir, opts)?;
+
+    log!("processing files listed in {:?}", list_path);
+    log!("output directory: {:?}", output_dir);
+    log!("using {} worker threads", opts.jobs);
+
+    let stats = run_workers(opts, None, |on_file| {
+        let mut read_error_count = 0;
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    read_error_count += 1;
+                    error!("error reading file list {list_path:?}: {e}");
+                    break;
+                }
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // URL 列表由 run_workers 在提交时展开
+            on_file(Path::new(line), output_dir);
+        }
+        read_error_count
+    });
+
+    Ok(stats)
+}
+
+/// `--inline` 的输入在报告和 manifest 中显示的路径
+const INLINE_INPUT: &str = "<inline>";
+
+/// 转换直接给出的牌谱 JSON（`-` 时从 stdin 读取）。与压缩包中的条目一样放入
+/// `archive_entries`，再按单个文件处理，因此各项过滤和检查都照常生效。
+fn process_inline(inline: &str, output_dir: &Path, opts: &ConvertOptions) -> Result<()> {
+    let body = log_source::read_inline(inline)?;
+    let input_path = PathBuf::from(INLINE_INPUT);
+    opts.archive_entries.lock().unwrap().insert(input_path.clone(), body.into_bytes());
+    log::set_current_file(Some(&input_path));
+    let result = process_file(&input_path, output_dir, opts);
+    opts.record(&input_path, &result);
+    result.map(|_| ())
+}
+
+/// 转换 zip 压缩包中的所有牌谱，不需要先解压。
+///
+/// 条目在提交时才读入内存，以 `压缩包路径/条目路径` 作为输入路径，条目的错误计入总的错误数。
+/// 开启 `opts.recursive` 时在输出目录中重建压缩包内的目录结构，否则全部输出到 `output_dir`。
+fn process_zip(zip_path: &Path, output_dir: &Path, opts: &ConvertOptions) -> Result<ConvertStats> {
+    let mut archive = ZipLogs::open(zip_path)?;
+
+    create_output_dir(output_dir, opts)?;
+
+    log!("processing {} entries in zip archive {:?}", archive.entry_count(), zip_path);
+    log!("output directory: {:?}", output_dir);
+    log!("using {} worker threads", opts.jobs);
+
+    // 显示进度时只读取目录得到总数，不必先把所有条目读入内存
+    let total = opts.progress.as_ref().map(|_| archive.count_logs());
+    let fail_entry = || {
+        if let Some(progress) = &opts.progress {
+            progress.finish(true);
+        }
+    };
+    let stats = run_workers(opts, total, |on_file| {
+        let mut read_error_count = 0;
+        for index in 0..archive.entry_count() {
+            if opts.aborted.load(Ordering::Relaxed) {
+                break;
+            }
+            let (name, body) = match archive.read_log(index) {
+                Ok(Some(entry)) => entry,
+                Ok(None) => continue,
+                Err(e) => {
+                    read_error_count += 1;
+                    fail_entry();
+                    error!("error reading zip archive {zip_path:?}: {e:#}");
+                    continue;
+                }
+            };
+
+            let entry_output_dir = match name.parent() {
+                Some(parent) if opts.recursive && !is_stdout(output_dir) => output_dir.join(parent),
+                _ => output_dir.to_path_buf(),
+            };
+            if let Err(e) = create_output_dir(&entry_output_dir, opts) {
+                read_error_count += 1;
+                fail_entry();
+                error!("{e:#}");
+                continue;
+            }
+
+            let input_path = zip_path.join(&name);
+            opts.archive_entries.lock().unwrap().insert(input_path.clone(), body);
+            on_file(&input_path, &entry_output_dir);
+        }
+        read_error_count
+    });
+
+    Ok(stats)
+}
+
+/// 下载并转换天凤牌谱 URL 列表 `list_path` 中的所有牌谱
+fn process_url_list(
+    list_path: &Path,
+    output_dir: &Path,
+    opts: &ConvertOptions,
+) -> Result<ConvertStats> {
+    create_output_dir(output_dir, opts)?;
+
+    log!("processing tenhou log URLs listed in {:?}", list_path);
+    log!("output directory: {:?}", output_dir);
+    log!("using {} worker threads", opts.jobs);
+
+    // 由 run_workers 在提交时逐行下载
+    let stats = run_workers(opts, None, |on_file| {
+        on_file(list_path, output_dir);
+        0
+    });
+    Ok(stats)
+}
+
+/// 使用 `opts.jobs` 个 worker 线程转换 `feed` 提交的文件，返回各类结果的计数。
+///
+/// `feed` 通过传入的回调提交 `(输入文件, 输出目录)`，返回提交过程中出现的错误数，
+/// 这些错误会计入最终的错误数。提交的 URL 列表在提交时才逐行下载、展开，下载与转换同时进行。
+///
+/// `total` 是 `feed` 会提交的输入数，用于 `--progress`。为 `None` 时先收集全部路径再提交，
+/// URL 列表按其中的行数计入总数。
+fn run_workers(
+    opts: &ConvertOptions,
+    total: Option<usize>,
+    feed: impl FnOnce(&mut dyn FnMut(&Path, &Path)) -> usize,
+) -> ConvertStats {
+    let processed_count = AtomicUsize::new(0);
+    let filtered_count = AtomicUsize::new(0);
+    let skipped_count = AtomicUsize::new(0);
+    let duplicate_count = AtomicUsize::new(0);
+    let duplicate_bytes = AtomicUsize::new(0);
+    let error_count = AtomicUsize::new(0);
+    let failures = Mutex::new(Vec::new());
+    let first_failure = Mutex::new(None);
+    let started_count = AtomicUsize::new(0);
+
+    // --limit：成功转换（指定 --limit-attempts 时为开始转换）的文件数已达到上限
+    let limit_reached = || {
+        let count = if opts.limit_attempts { &started_count } else { &processed_count };
+        opts.limit.is_some_and(|limit| count.load(Ordering::Relaxed) >= limit)
+    };
+
+    // 使用有界队列分发任务，避免目录很大时一次性占用过多内存
+    // 任务带有提交顺序的序号，供 --concat 按输入顺序写出
+    let (tx, rx) = mpsc::sync_channel::<(usize, PathBuf, PathBuf)>(opts.jobs * 2);
+    let rx = Mutex::new(rx);
+
+    let feed_error_count = thread::scope(|s| {
+        for _ in 0..opts.jobs {
+            s.spawn(|| loop {
+                // 只在取任务时持有锁
+                let job = rx.lock().unwrap().recv();
+                let Ok((index, path, output_dir)) = job else {
+                    break;
+                };
+                // --fail-fast 时出错后、--limit 达到上限后只清空队列，不再转换。
+                // 计开始转换的文件数时先占一个名额，多个 worker 同时开始也不会超过上限
+                let started = started_count.fetch_add(1, Ordering::Relaxed);
+                let over_limit = opts.limit.is_some_and(|limit| {
+                    let count = if opts.limit_attempts {
+                        started
+                    } else {
+                        processed_count.load(Ordering::Relaxed)
+                    };
+                    count >= limit
+                });
+                let commit = || opts.concat.as_ref().map_or(Ok(()), |c| c.commit(index, &path));
+                if opts.aborted.load(Ordering::Relaxed) || over_limit {
+                    if let Err(e) = commit() {
+                        error!("{e:#}");
+                    }
+                    if let Some(progress) = &opts.progress {
+                        progress.finish(false);
+                    }
+                    continue;
+                }
+
+                log::set_current_file(Some(&path));
+                if let Some(progress) = &opts.progress {
+                    progress.start(&path);
+                }
+                let stopwatch = Stopwatch::start();
+                let result = process_file(&path, &output_dir, opts);
+                // 失败的输入也要提交，写出失败时算作该输入失败
+                let result = commit().and(result);
+                opts.record(&path, &result);
+                if let Some(progress) = &opts.progress {
+                    progress.finish(result.is_err());
+                }
+                match result {
+                    Ok(FileOutcome::Converted(converted)) => {
+                        processed_count.fetch_add(1, Ordering::Relaxed);
+                        if let Some(samples) = &opts.bench_samples {
+                            samples.lock().unwrap().push((stopwatch.elapsed(), converted.events));
+                        }
+                    }
+                    Ok(FileOutcome::Filtered) => {
+                        filtered_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(FileOutcome::Skipped) => {
+                        skipped_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(FileOutcome::Duplicate { bytes, .. }) => {
+                        duplicate_count.fetch_add(1, Ordering::Relaxed);
+                        duplicate_bytes.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    Ok(FileOutcome::Games { converted }) => {
+                        processed_count.fetch_add(converted, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        if opts.fail_fast && !opts.aborted.swap(true, Ordering::Relaxed) {
+                            *first_failure.lock().unwrap() = Some(path.clone());
+                        }
+                        match e.downcast::<GamesFailed>() {
+                            // 各场的错误已在 process_games 中输出
+                            Ok(games) => {
+                                error_count.fetch_add(games.failures.len(), Ordering::Relaxed);
+                                failures.lock().unwrap().extend(games.failures);
+                            }
+                            Err(e) => {
+                                error_count.fetch_add(1, Ordering::Relaxed);
+                                let message = format!("{e:#}");
+                                failures
+                                    .lock()
+                                    .unwrap()
+                                    .push(ConvertFailure { path: path.clone(), message });
+                                // 每条错误只输出一次，多线程输出不会在行中交错
+                                let message = format!("error processing {:?}: {:#}", path, e);
+                                match &opts.progress {
+                                    Some(progress) => progress.eprintln(&message),
+                                    None => error!("{message}"),
+                                }
+                            }
+                        }
+                    }
+                }
+                log::set_current_file(None);
+            });
+        }
+
+        let stopped = || opts.aborted.load(Ordering::Relaxed) || limit_reached();
+        let skip = |count| {
+            if let Some(progress) = &opts.progress {
+                progress.advance(count, 0);
+            }
+        };
+        let mut next_index = 0;
+        let mut send_file = |path: &Path, output_dir: &Path| {
+            if stopped() {
+                skip(1);
+                return;
+            }
+            let job = (next_index, path.to_path_buf(), output_dir.to_path_buf());
+            tx.send(job).expect("worker threads exited unexpectedly");
+            next_index += 1;
+        };
+        let mut url_list_error_count = 0;
+        let mut send = |path: &Path, output_dir: &Path| {
+            if !is_url_list(path) {
+                send_file(path, output_dir);
+            } else if opts.offline {
+                log!("skipping {path:?}: URL lists are not downloaded here");
+                skip(count_url_list_entries(path));
+            } else if stopped() {
+                skip(count_url_list_entries(path));
+            } else {
+                let errors = feed_url_list(path, output_dir, &opts.download, &mut send_file);
+                url_list_error_count += errors;
+                if let Some(progress) = &opts.progress {
+                    progress.advance(errors, errors);
+                }
+            }
+        };
+        let feed_error_count = match (&opts.progress, total) {
+            (Some(progress), Some(total)) => {
+                progress.set_total(total);
+                // 不输出每个文件的进度信息，以免打乱进度行
+                log::set_quiet(true);
+                feed(&mut send)
+            }
+            // 显示进度时先收集全部路径以得到总数，再提交给 worker。
+            // 只收集路径，压缩包条目和 URL 列表中的牌谱仍在提交时才读取、下载
+            (Some(progress), None) => {
+                let mut jobs = Vec::new();
+                let feed_error_count = feed(&mut |path, output_dir| {
+                    jobs.push((path.to_path_buf(), output_dir.to_path_buf()));
+                });
+                let count = |path: &Path| {
+                    if is_url_list(path) { count_url_list_entries(path) } else { 1 }
+                };
+                let total = jobs.iter().map(|(path, _)| count(path)).sum();
+                progress.set_total(total);
+                log::set_quiet(true);
+                for (path, output_dir) in &jobs {
+                    send(path, output_dir);
+                }
+                feed_error_count
+            }
+            (None, _) => feed(&mut send),
+        };
+
+        // 关闭队列，worker 处理完剩余任务后退出
+        drop(tx);
+        feed_error_count + url_list_error_count
+    });
+
+    if let Some(progress) = &opts.progress {
+        progress.done();
+        log::set_quiet(false);
+    }
+    if let Some(limit) = opts.limit.filter(|_| limit_reached()) {
+        log!("stopped after {limit} files (--limit), the remaining inputs were not converted");
+    }
+
+    ConvertStats {
+        processed: processed_count.into_inner(),
+        filtered: filtered_count.into_inner(),
+        skipped: skipped_count.into_inner(),
+        duplicates: duplicate_count.into_inner(),
+        duplicate_bytes: duplicate_bytes.into_inner(),
+        errors: error_count.into_inner() + feed_error_count,
+        failures: failures.into_inner().unwrap(),
+        first_failure: first_failure.into_inner().unwrap(),
+    }
+}
+
+/// 创建输出目录（如果不存在）。输出到 stdout 或 dry run 时不做任何事。
+fn create_output_dir(output_dir: &Path, opts: &ConvertOptions) -> Result<()> {
+    if opts.dry_run || opts.concat.is_some() || is_stdout(output_dir) {
+        return Ok(());
+    }
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("failed to create output directory: {:?}", output_dir))
+}
+
+/// 遍历 `input_dir` 中待转换的文件，对每个文件调用 `on_file(输入文件, 输出目录)`。
+///
+/// 开启 `opts.recursive` 时会进入子目录，并在 `output_dir` 下创建相同的目录结构。
+/// 已访问过的目录按规范化路径记录在 `visited_dirs` 中，用于跳过符号链接造成的循环。
+/// 读取目录本身失败时不会中断遍历，而是累加到 `error_count`。
+/// 天凤牌谱 URL 列表（见 [`is_url_list`]）原样提交，由 [`run_workers`] 下载其中的牌谱。
+fn visit_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    opts: &ConvertOptions,
+    visited_dirs: &mut HashSet<PathBuf>,
+    error_count: &mut usize,
+    on_file: &mut dyn FnMut(&Path, &Path),
+) {
+    match canonicalize!(input_dir) {
+        Ok(canonical) => {
+            if !visited_dirs.insert(canonical) {
+                log!("skipping already visited directory (symlink loop?): {input_dir:?}");
+                return;
+            }
+        }
+        Err(e) => {
+            *error_count += 1;
+            error!("error reading directory {input_dir:?}: {e:#}");
+            return;
+        }
+    }
+
+    // 遍历输入目录
+    let entries = match fs::read_dir(input_dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            *error_count += 1;
+            error!("error reading directory {input_dir:?}: {e}");
+            return;
+        }
+    };
+
+    for entry in entries {
+        if opts.aborted.load(Ordering::Relaxed) {
+            return;
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                *error_count += 1;
+                error!("error reading directory entry: {}", e);
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        if path.is_dir() {
+            if !opts.recursive {
+                log!("skipping subdirectory: {:?}", path);
+                continue;
+            }
+
+            // 在输出目录中创建对应的子目录
+            let sub_output_dir = if is_stdout(output_dir) {
+                output_dir.to_path_buf()
+            } else {
+                output_dir.join(entry.file_name())
+            };
+            if let Err(e) = create_output_dir(&sub_output_dir, opts) {
+                *error_count += 1;
+                error!("{e:#}");
+                continue;
+            }
+            visit_directory(&path, &sub_output_dir, opts, visited_dirs, error_count, on_file);
+            continue;
+        }
+
+        // 可以根据文件扩展名过滤文件
+        if !is_log_extension(&path) {
+            continue;
+        }
+
+        // URL 列表由 run_workers 在提交时展开
+        on_file(&path, output_dir);
+    }
+}
+
+/// 将 manifest 写入 `path`，条目按输入路径排序
+fn write_manifest(path: &Path, mut entries: Vec<ManifestEntry>) -> Result<()> {
+    entries.sort_by(|a, b| a.input.cmp(&b.input));
+
+    let file = File::create(path)
+        .with_context(|| format!("failed to create manifest file: {path:?}"))?;
+    json::to_writer_pretty(BufWriter::new(file), &json::json!({ "files": entries }))
+        .with_context(|| format!("failed to write manifest file: {path:?}"))
+}
+
+/// 将座位统计写入 `path`，并在日志中输出一份表格
+/// 写出 `--efficiency-export`，按输入路径排序
+fn write_efficiency(path: &Path, mut games: Vec<(PathBuf, Vec<json::Value>)>) -> Result<()> {
+    games.sort_by(|a, b| a.0.cmp(&b.0));
+    let decisions: usize = games.iter().map(|(_, decisions)| decisions.len()).sum();
+    log!("efficiency export: {decisions} discards in {} games", games.len());
+
+    let games: Vec<_> = games
+        .into_iter()
+        .map(|(input, decisions)| json::json!({ "input": input, "decisions": decisions }))
+        .collect();
+    let file = File::create(path)
+        .with_context(|| format!("failed to create efficiency export file: {path:?}"))?;
+    json::to_writer_pretty(BufWriter::new(file), &json::json!({ "games": games }))
+        .with_context(|| format!("failed to write efficiency export file: {path:?}"))
+}
+
+fn write_seat_stats(path: &Path, stats: &BatchStats) -> Result<()> {
+    log!("seat stats over {} games, {} kyoku:", stats.games, stats.kyoku);
+    log!("seat  agari  tsumo    ron  houjuu  riichi");
+    for (seat, s) in stats.seats.iter().enumerate() {
+        log!("{seat:>4}  {:>5}  {:>5}  {:>5}  {:>6}  {:>6}",
+             s.agari, s.tsumo, s.ron, s.houjuu, s.riichi);
+    }
+
+    let file = File::create(path)
+        .with_context(|| format!("failed to create seat stats file: {path:?}"))?;
+    json::to_writer_pretty(BufWriter::new(file), stats)
+        .with_context(|| format!("failed to write seat stats file: {path:?}"))
+}
+
+/// 将按玩家名汇总的打法倾向统计写入 `path`
+fn write_player_stats(path: &Path, stats: &PlayerTendencies) -> Result<()> {
+    log!("player stats for {} players", stats.players.len());
+
+    let file = File::create(path)
+        .with_context(|| format!("failed to create player stats file: {path:?}"))?;
+    json::to_writer_pretty(BufWriter::new(file), stats)
+        .with_context(|| format!("failed to write player stats file: {path:?}"))
+}
+
+/// 将各役的出现次数写入 `path`，并在日志中输出出现最多的几个役
+fn write_yaku_stats(path: &Path, stats: &YakuStats) -> Result<()> {
+    log!("yaku stats over {} agari:", stats.agari);
+    let mut yaku: Vec<_> = stats.yaku.iter().collect();
+    yaku.sort_by_key(|(_, count)| Reverse(count.count));
+    for (name, count) in yaku.iter().take(10) {
+        log!("{:>6}  {name}", count.count);
+    }
+
+    let file = File::create(path)
+        .with_context(|| format!("failed to create yaku stats file: {path:?}"))?;
+    json::to_writer_pretty(BufWriter::new(file), stats)
+        .with_context(|| format!("failed to write yaku stats file: {path:?}"))
+}
+
+/// Batch convert tenhou.net/6 logs into mjai logs.
+#[derive(Parser)]
+#[command(
+    name = "tenhou2mjai",
+    version,
+    args_conflicts_with_subcommands = true,
+    override_usage = "tenhou2mjai [OPTIONS] <INPUT> <OUTPUT>\n       \
+                      tenhou2mjai [OPTIONS] --files-from <LIST> <OUTPUT>\n       \
+                      tenhou2mjai [OPTIONS] --concat <FILE> <INPUT>\n       \
+                      tenhou2mjai [OPTIONS] --stdout <INPUT>\n       \
+                      tenhou2mjai [OPTIONS] --inline <JSON>\n       \
+                      tenhou2mjai <COMMAND>"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// 不带子命令时与 `convert` 相同，兼容原来的 `<INPUT> <OUTPUT>` 用法。
+    /// 第一个位置参数与子命令同名时（如名为 `bench` 的目录）按子命令解析，需写成 `convert bench`
+    #[command(flatten)]
+    convert: ConvertArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert tenhou.net/6 logs into mjai logs. This is the default when no
+    /// subcommand is given.
+    Convert(ConvertArgs),
+    /// Print a JSON Schema describing the mjai events this program emits.
+    Schema,
+    /// Measure conversion throughput on a log file, or a directory or .zip
+    /// archive of logs. Logs are converted in memory and nothing is written.
+    Bench(BenchArgs),
+    /// Convert a few logs embedded in the binary and check the result against
+    /// the original logs as --verify and --check-wall do. Exits with an
+    /// error if any of them fails.
+    Selftest,
+    /// Check whether each log in a file, directory or .zip archive is a
+    /// parseable tenhou.net/6 log and converts cleanly, without writing any
+    /// mjai output. URL lists are not downloaded. Exits with an error if any
+    /// of them fails.
+    Validate(ValidateArgs),
+    /// Convert the mjai log of a four-player game back into a tenhou.net/6
+    /// log and print it to stdout, e.g. to watch bot self-play in tenhou's
+    /// replayer. Fu, han, yaku and the kind of draw are not recorded in mjai
+    /// logs and are left out.
+    ToTenhou(ToTenhouArgs),
+    /// Print an mjai log as a human-readable turn-by-turn transcript, with
+    /// the starting hands, the results and the final hands of every kyoku.
+    Pretty(PrettyArgs),
+    /// Print the shanten number and ukeire (the tiles that lower it) of a
+    /// hand. For a hand of 3n+2 tiles, every discard is listed with the
+    /// shanten and ukeire after it, best first.
+    Tehai(TehaiArgs),
+}
+
+#[derive(Args)]
+struct TehaiArgs {
+    /// The hand, e.g. `123m456p789s11z22z`: numbers followed by their suit,
+    /// m, p or s (0 is a red five) or z for honors (1z to 7z are East,
+    /// South, West, North, White, Green, Red). A JSON array of mjai tiles
+    /// such as `["1m","2m","E"]` is accepted as well. At most 14 tiles;
+    /// fewer than 13 are taken as a hand with called melds.
+    #[arg(long)]
+    hand: String,
+}
+
+#[derive(Args)]
+struct PrettyArgs {
+    /// An mjai log with one event per line, or `-` to read it from stdin.
+    input: PathBuf,
+}
+
+#[derive(Args)]
+struct ToTenhouArgs {
+    /// An mjai log with one event per line, or `-` to read it from stdin.
+    input: PathBuf,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// A tenhou.net/6 log file, or a directory or .zip archive containing
+    /// them.
+    input: PathBuf,
+
+    /// Descend into subdirectories.
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Number of files to check concurrently. Defaults to the number of
+    /// CPUs.
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Print the report as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// A tenhou.net/6 log file, or a directory or .zip archive of them.
+    input: PathBuf,
+
+    /// Descend into subdirectories.
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Number of files to convert concurrently. Defaults to the number of
+    /// CPUs.
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// A tenhou.net/6 log file, or a directory or .zip archive containing
+    /// them (.json, .txt or gzipped .gz). A tenhou.net log URL or log ID is
+    /// downloaded and converted instead, as is every log in a .txt file
+    /// listing one URL per line. Omitted when --files-from is given.
+    input: Option<PathBuf>,
+
+    /// Directory to write the converted mjai logs into. Use `-` to write
+    /// NDJSON to stdout instead; when converting a directory each file is
+    /// then preceded by a `# <input path>` marker line.
+    output: Option<PathBuf>,
+
+    /// Read newline-separated input paths from this file (`-` for stdin)
+    /// instead of INPUT. Blank lines and lines starting with `#` are
+    /// ignored.
+    #[arg(long, value_name = "LIST")]
+    files_from: Option<PathBuf>,
+
+    /// Convert the raw log JSON given as this argument (`-` to read it from
+    /// stdin) instead of INPUT, and print the mjai events to stdout.
+    #[arg(long, value_name = "JSON", conflicts_with_all = ["input", "output", "files_from"])]
+    inline: Option<String>,
+
+    /// Convert the single log file INPUT and stream its events to stdout
+    /// instead of taking an output directory; directories, .zip archives
+    /// and URL lists are rejected. Log messages go to stderr. Exits with a
+    /// non-zero status, without writing anything, if the log cannot be
+    /// parsed or converted or is filtered out, or if the file contains
+    /// several games and --game-index does not select one.
+    #[arg(
+        long,
+        conflicts_with_all = ["output", "files_from", "concat", "split_kyoku", "output_template"]
+    )]
+    stdout: bool,
+
+    /// Descend into subdirectories, mirroring the input tree under the
+    /// output directory.
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Number of files to convert concurrently. Defaults to the number of
+    /// CPUs.
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Run the full conversion but do not write any output, only report
+    /// which logs would fail.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Stop at the first file that fails to convert instead of converting
+    /// the rest and reporting all errors at the end.
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Stop after N files were converted successfully, e.g. for a quick
+    /// smoke test with --dry-run. Files already being converted by other
+    /// jobs when the limit is reached still finish.
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Count every file whose conversion was started towards --limit, not
+    /// only the successful ones.
+    #[arg(long, requires = "limit")]
+    limit_attempts: bool,
+
+    /// Only print errors, not per-file progress.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Additionally report how long each file took to convert.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Format of the progress, warning and error messages printed to stderr.
+    #[arg(long, value_enum, default_value_t = LogFormat::Plain)]
+    log_format: LogFormat,
+
+    /// Do not color the summary printed at the end. Colors are also
+    /// disabled when stderr is not a terminal, NO_COLOR is set or
+    /// --log-format is json.
+    #[arg(long)]
+    no_color: bool,
+
+    /// Show the number of files converted out of the total, the current
+    /// file and the errors so far instead of per-file progress messages.
+    /// Updated in place when stderr is a terminal, otherwise printed every
+    /// 10 seconds.
+    #[arg(long, conflicts_with_all = ["quiet", "verbose"])]
+    progress: bool,
+
+    /// After converting, check the mjai events against the original log
+    /// (number of kyoku, scores, dora indicators, score deltas, every
+    /// player's final hand and the final scores) and treat any mismatch as
+    /// an error.
+    #[arg(long)]
+    verify: bool,
+
+    /// After converting, check that no tile shows up in a kyoku more often
+    /// than a full set holds (4 of each, 1 of each red five) and that every
+    /// discard and call uses tiles from the hand. Any problem is an error.
+    #[arg(long)]
+    check_wall: bool,
+
+    /// Convert the complete kyoku of a truncated log (e.g. an interrupted
+    /// download) with a warning, instead of failing the whole file.
+    #[arg(long)]
+    best_effort: bool,
+
+    /// Treat oddities in a log that are normally only warned about or
+    /// defaulted as errors: undecodable player names, no game timestamp in
+    /// the log or file name, or a rule name without a known room. The
+    /// affected files count as errors.
+    #[arg(long, conflicts_with = "best_effort")]
+    strict: bool,
+
+    /// Replace the player names with `player0` to `player3` in the output,
+    /// e.g. to share converted logs publicly.
+    #[arg(long)]
+    anonymize: bool,
+
+    /// With --anonymize, replace each name with a keyed BLAKE3 hash of the
+    /// name, using this string as the key, so that a player keeps the same
+    /// name across logs without revealing it. Keep the salt secret: anyone
+    /// who knows it can test guessed names against the hashes.
+    #[arg(long, value_name = "SALT", requires = "anonymize")]
+    anonymize_salt: Option<String>,
+
+    /// Parse plain JSON logs straight from the file with a read buffer of
+    /// this many bytes, instead of reading the whole file into memory first.
+    /// Gzipped logs and zip entries are still read in full. Off by default,
+    /// since reading the whole file is faster for logs of the usual size.
+    #[arg(long, value_name = "BYTES")]
+    read_buffer_size: Option<NonZeroUsize>,
+
+    /// Skip inputs whose output file exists and is newer than the input.
+    #[arg(long)]
+    incremental: bool,
+
+    /// Reconvert every input even with --incremental or --checkpoint.
+    #[arg(long)]
+    force: bool,
+
+    /// Keep the original extension in the output file name, e.g.
+    /// `game.txt` -> `game.txt.json` instead of `game.json`.
+    #[arg(long)]
+    keep_extension: bool,
+
+    /// Suffix appended to the output file name, e.g. `.mjai.json`.
+    #[arg(long, default_value = ".json")]
+    output_suffix: String,
+
+    /// Name output files after this template instead of the input file
+    /// name, e.g. `{date}/{players}.json`. Placeholders: {stem} (input file
+    /// name without extension), {game_id}, {date} (YYYY-MM-DD, Japan time),
+    /// {length} (hanchan or tonpuusen) and {players}. Characters that are
+    /// not allowed in file names are replaced by `_` in substituted values.
+    /// Overrides --keep-extension and --output-suffix.
+    #[arg(long, value_name = "TEMPLATE")]
+    output_template: Option<String>,
+
+    /// Spread the output files over N subdirectories of the output
+    /// directory, chosen by a hash of the output file name and named by a
+    /// fixed-width hex number (`00` to `ff` for 256), so that no single
+    /// directory gets too many entries.
+    #[arg(long, value_name = "N")]
+    shard: Option<NonZeroUsize>,
+
+    /// Write every converted game into this single file instead of one
+    /// output file per input, in input order and each game complete with
+    /// its start_game and end_game, e.g. to feed a whole directory into one
+    /// training run. Replaces <OUTPUT>. The file is rewritten on every run,
+    /// so it cannot be combined with --checkpoint.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "inline",
+            "split_kyoku",
+            "incremental",
+            "output_template",
+            "checkpoint",
+        ]
+    )]
+    concat: Option<PathBuf>,
+
+    /// Format of the converted mjai logs.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Ndjson)]
+    output_format: OutputFormat,
+
+    /// Add the game start time to every start_game event as `started_at`
+    /// (RFC 3339, whole seconds), read from the log ID or file name, or the
+    /// file's modification time if neither has one. Ignored with
+    /// `--output-format hands`.
+    #[arg(long)]
+    start_time: bool,
+
+    /// Write every kyoku to its own file, each a standalone mjai log with
+    /// the game's start_game and an end_game. Files are named after the
+    /// round and honba, e.g. `game_E1.json`, `game_E1_honba1.json` for a
+    /// renchan or draw, then `game_E2.json`.
+    #[arg(long, conflicts_with = "incremental")]
+    split_kyoku: bool,
+
+    /// Only convert games of this length; other logs are counted as
+    /// filtered.
+    #[arg(long, value_enum, default_value_t = GameLengthFilter::All)]
+    game_length: GameLengthFilter,
+
+    /// Only convert games played in this room, read from the log's rule
+    /// name; other logs are counted as filtered. Logs whose room cannot be
+    /// determined are only converted with `all`.
+    #[arg(long, value_enum, default_value_t = RoomFilter::All)]
+    room: RoomFilter,
+
+    /// Only convert games with at least one abortive draw, or that ended
+    /// before every round of their length was played (e.g. someone went
+    /// bust); other logs are counted as filtered, so the processed count is
+    /// the number of matching games.
+    #[arg(long)]
+    only_incomplete: bool,
+
+    /// For files that bundle several games (a JSON array of logs, or logs
+    /// concatenated one after another), only convert the game at this
+    /// 0-based index. Without it every game is converted to its own output,
+    /// named after the input with a `_game0`, `_game1`, ... suffix.
+    #[arg(long, value_name = "K")]
+    game_index: Option<usize>,
+
+    /// Only convert games played on or after this date (YYYY-MM-DD, Japan
+    /// time), taken from the log ID in the log or its file name.
+    #[arg(long, value_name = "DATE")]
+    since: Option<NaiveDate>,
+
+    /// Only convert games played on or before this date (YYYY-MM-DD, Japan
+    /// time).
+    #[arg(long, value_name = "DATE")]
+    until: Option<NaiveDate>,
+
+    /// With --since or --until, also convert logs whose date cannot be
+    /// determined instead of skipping them.
+    #[arg(long)]
+    include_undated: bool,
+
+    /// Write per-seat agari (tsumo/ron), deal-in and riichi counts over all
+    /// converted logs to this JSON file.
+    #[arg(long, value_name = "FILE")]
+    seat_stats: Option<PathBuf>,
+
+    /// Write per-player tendencies (average riichi turn, fold rate against
+    /// riichi, call rate and win turn distribution) over all converted logs
+    /// to this JSON file, keyed by player name.
+    #[arg(long, value_name = "FILE")]
+    player_stats: Option<PathBuf>,
+
+    /// Write how often each yaku (including dora and yakuman) appeared in
+    /// the wins of all converted logs, with its average han, to this JSON
+    /// file.
+    #[arg(long, value_name = "FILE")]
+    yaku_stats: Option<PathBuf>,
+
+    /// Write, for every discard made outside riichi in all converted logs,
+    /// the hand before it, the shanten and ukeire after the actual discard,
+    /// and the discards with the most ukeire, to this JSON file.
+    #[arg(long, value_name = "FILE")]
+    efficiency_export: Option<PathBuf>,
+
+    /// Write a JSON manifest of every input (output path, status, number of
+    /// events, game length, game start time, duplicated file and error if
+    /// any) to this file.
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Record every successfully converted input in this file as it
+    /// finishes, and skip inputs already recorded there. Lets an
+    /// interrupted batch resume where it stopped.
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Cache the converted events of every log in this directory, keyed by
+    /// a hash of the log content, and reuse them on later runs instead of
+    /// converting again.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// How many times to retry a failed tenhou.net download (network
+    /// errors, HTTP 429 and 5xx) before giving up.
+    #[arg(long, default_value_t = 3)]
+    max_retries: u32,
+
+    /// Seconds to wait before the first download retry, doubled after every
+    /// attempt. A Retry-After header sent with HTTP 429 takes precedence.
+    #[arg(long, value_name = "SECONDS", default_value_t = 1)]
+    retry_delay: u64,
+
+    /// User-Agent header to send when downloading from tenhou.net.
+    #[arg(long, value_name = "AGENT")]
+    user_agent: Option<String>,
+
+    /// Extra header to send when downloading from tenhou.net, as
+    /// `Name: value`, e.g. `--header "Cookie: ..."` for a session cookie.
+    /// Can be given multiple times. Header values are never logged.
+    #[arg(long = "header", value_name = "HEADER", value_parser = download::parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Skip logs whose game content is identical to one already seen in this
+    /// run, regardless of file name or formatting, and report them as
+    /// duplicates.
+    #[arg(long)]
+    dedup: bool,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Convert(args)) => convert(args),
+        Some(Command::Schema) => {
+            let mut stdout = io::stdout().lock();
+            json::to_writer_pretty(&mut stdout, &mjai_event_schema())?;
+            writeln!(stdout)?;
+            Ok(())
+        }
+        Some(Command::Bench(args)) => bench(&args),
+        Some(Command::Selftest) => selftest(),
+        Some(Command::Validate(args)) => validate(&args),
+        Some(Command::ToTenhou(args)) => to_tenhou(&args),
+        Some(Command::Pretty(args)) => pretty(&args),
+        Some(Command::Tehai(args)) => tehai(&args),
+        None => convert(cli.convert),
+    }
+}
+
+/// `bench` 子命令：以 dry run 方式转换所有牌谱，输出吞吐量和单个文件耗时的分布
+fn bench(args: &BenchArgs) -> Result<()> {
+    log::set_quiet(true);
+
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let opts = ConvertOptions {
+        recursive: args.recursive,
+        jobs,
+        dry_run: true,
+        output_suffix: ".json".to_owned(),
+        // 下载耗时与转换无关，计时时不处理 URL 列表
+        offline: true,
+        bench_samples: Some(Mutex::default()),
+        ..Default::default()
+    };
+
+    let source = LogSource::from_input(&args.input);
+    if matches!(source, LogSource::Tenhou(_) | LogSource::UrlList(_)) {
+        bail!("{:?} is a tenhou log URL or URL list, bench does not download logs", args.input);
+    }
+
+    // 不写出任何文件，输出目录只用于生成输出路径
+    let output = Path::new("-");
+    let stopwatch = Stopwatch::start();
+    let result = match &source {
+        LogSource::Directory(input) => process_directory(input, output, &opts),
+        LogSource::Zip(input) => process_zip(input, output, &opts),
+        // 单个文件同样交给 worker 转换，耗时才会被记录
+        LogSource::File(input) => Ok(run_workers(&opts, None, |on_file| {
+            on_file(input, output);
+            0
+        })),
+        LogSource::Tenhou(_) | LogSource::UrlList(_) | LogSource::Inline(_) => {
+            unreachable!("bench inputs are read from disk")
+        }
+    };
+    let wall = stopwatch.elapsed();
+    let result = result.and_then(|stats| report_stats(&stats));
+
+    let mut samples = opts.bench_samples.unwrap().into_inner().unwrap();
+    samples.sort_unstable();
+    let files = samples.len();
+    let events: usize = samples.iter().map(|&(_, events)| events).sum();
+    let secs = wall.as_seconds_f64().max(f64::EPSILON);
+    // 最近秩法求百分位数
+    let percentile = |p: f64| {
+        let rank = ((p * files as f64).ceil() as usize).clamp(1, files.max(1));
+        samples.get(rank - 1).map_or(0.0, |&(latency, _)| latency.as_seconds_f64() * 1000.0)
+    };
+
+    println!("converted {files} files ({events} events) in {secs:.2} s using {jobs} threads");
+    println!("throughput: {:.1} files/s, {:.0} events/s",
+             files as f64 / secs,
+             events as f64 / secs);
+    println!("per-file latency: p50 {:.2} ms, p99 {:.2} ms", percentile(0.5), percentile(0.99));
+
+    result
+}
+
+/// `to-tenhou` 子命令：将一场比赛的 mjai 牌谱转换回 tenhou.net/6 牌谱，写到 stdout
+fn to_tenhou(args: &ToTenhouArgs) -> Result<()> {
+    let events = read_mjai_log(&args.input)?;
+    let log = mjai_to_tenhou(&events)
+        .with_context(|| format!("failed to convert {:?} to a tenhou.net/6 log", args.input))?;
+    let mut stdout = io::stdout().lock();
+    json::to_writer(&mut stdout, &log)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// `pretty` 子命令：将 mjai 牌谱以逐巡记录的形式输出到 stdout，不做任何转换
+fn pretty(args: &PrettyArgs) -> Result<()> {
+    let events = read_mjai_log(&args.input)?;
+    write_transcript(&mut BufWriter::new(io::stdout().lock()), &events)
+        .with_context(|| format!("failed to replay {:?}", args.input))
+}
+
+/// `tehai` 子命令：输出手牌的向听数与进张
+fn tehai(args: &TehaiArgs) -> Result<()> {
+    let tiles = parse_hand(&args.hand).with_context(|| format!("invalid hand {:?}", args.hand))?;
+    let describe = |ukeire: &Ukeire| {
+        let tiles = match ukeire.tiles.as_slice() {
+            [] => "none".to_owned(),
+            tiles => tiles.join(" "),
+        };
+        format!("shanten {}, ukeire {tiles} ({} tiles)", ukeire.shanten, ukeire.count)
+    };
+    if tiles.len() % 3 == 1 {
+        println!("{}", describe(&ukeire(&tiles)?));
+    } else {
+        println!("shanten {}", shanten(&tiles)?);
+        for (discard, ukeire) in discard_ukeire(&tiles)? {
+            println!("discard {discard}: {}", describe(&ukeire));
+        }
+    }
+    Ok(())
+}
+
+/// 读取 NDJSON 格式的 mjai 牌谱，`path` 为 `-` 时从 stdin 读取
+fn read_mjai_log(path: &Path) -> Result<Vec<Event>> {
+    let body = if path == Path::new("-") {
+        let mut body = String::new();
+        io::stdin().read_to_string(&mut body).context("failed to read mjai log from stdin")?;
+        body
+    } else {
+        read_log_file(path, None)?
+    };
+    parse_mjai_events(&body).with_context(|| format!("failed to parse mjai log: {path:?}"))
+}
+
+/// `selftest` 子命令使用的内嵌牌谱：名称、天凤牌谱。
+///
+/// 这里只放天凤牌谱，不放手写的期望 mjai 事件：转换结果是否正确由 [`verify_events`] 和
+/// [`check_wall`] 对照原始牌谱检查。
+const SELFTEST_FIXTURES: [(&str, &str); 17] = [
+    ("dealer_tsumo", include_str!("fixtures/dealer_tsumo.json")),
+    ("chiitoitsu_ron", include_str!("fixtures/chiitoitsu_ron.json")),
+    ("abortive_draws", include_str!("fixtures/abortive_draws.json")),
+    ("three_kans", include_str!("fixtures/three_kans.json")),
+    ("double_riichi_kans", include_str!("fixtures/double_riichi_kans.json")),
+    ("red_fives", include_str!("fixtures/red_fives.json")),
+    ("chankan", include_str!("fixtures/chankan.json")),
+    ("riichi_sticks", include_str!("fixtures/riichi_sticks.json")),
+    ("nagashi_mangan", include_str!("fixtures/nagashi_mangan.json")),
+    ("dealer_renchan", include_str!("fixtures/dealer_renchan.json")),
+    ("double_ron", include_str!("fixtures/double_ron.json")),
+    ("head_bump", include_str!("fixtures/head_bump.json")),
+    ("disconnected_seat", include_str!("fixtures/disconnected_seat.json")),
+    ("double_riichi", include_str!("fixtures/double_riichi.json")),
+    ("ippatsu", include_str!("fixtures/ippatsu.json")),
+    ("chiihou", include_str!("fixtures/chiihou.json")),
+    ("four_melds_rinshan", include_str!("fixtures/four_melds_rinshan.json")),
+];
+
+/// `selftest` 子命令：转换所有内嵌牌谱，逐个输出是否通过
+fn selftest() -> Result<()> {
+    let mut failed = 0;
+    for (name, log) in SELFTEST_FIXTURES {
+        match check_fixture(log) {
+            Ok(()) => println!("pass  {name}"),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {name}: {e:#}");
+            }
+        }
+    }
+
+    ensure!(failed == 0, "{failed} of {} self-test fixtures failed", SELFTEST_FIXTURES.len());
+    Ok(())
+}
+
+/// 转换一个内嵌牌谱，做与 `--verify` 和 `--check-wall` 相同的检查
+fn check_fixture(log: &str) -> Result<()> {
+    let raw = parse_tenhou_json(log)?;
+    let events = convert_log(log)?;
+    if let Some(mismatch) = verify_events(&raw, &events)?.first() {
+        bail!("verification failed: {mismatch}");
+    }
+    if let Some(mismatch) = check_wall(&events)?.first() {
+        bail!("wall check failed: {mismatch}");
+    }
+    Ok(())
+}
+
+/// `validate` 子命令中一个文件的检查结果
+#[derive(Serialize)]
+struct Validation {
+    input: PathBuf,
+    /// 是否通过转换前的结构检查，即为可以解析的 tenhou.net/6 牌谱
+    parseable: bool,
+    /// 是否能转换为 mjai 事件
+    converts: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    events: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// `validate` 子命令：检查每个牌谱能否解析和转换，输出表格或 JSON 报告，不写出任何文件。
+///
+/// 与 `convert --dry-run` 走相同的转换流程，压缩包中的条目和多场比赛的文件中的每场都分别检查。
+/// 不下载 URL 列表中的牌谱，也不应用任何过滤条件。
+fn validate(args: &ValidateArgs) -> Result<()> {
+    // 每个输入的结果都在报告中，不再逐个输出进度信息
+    log::set_quiet(true);
+
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let opts = ConvertOptions {
+        recursive: args.recursive,
+        jobs,
+        dry_run: true,
+        output_suffix: ".json".to_owned(),
+        offline: true,
+        manifest: Some(Mutex::default()),
+        ..Default::default()
+    };
+
+    // 不写出任何文件，输出目录只用于生成输出路径
+    let output = Path::new("-");
+    let input = &args.input;
+    let stats = if input.is_dir() {
+        process_directory(input, output, &opts)?
+    } else if is_zip_extension(input) {
+        process_zip(input, output, &opts)?
+    } else {
+        ensure!(!is_url_list(input), "{input:?} is a URL list, validate does not download logs");
+        // 单个文件的结果只记录在 manifest 中
+        opts.record(input, &process_file(input, output, &opts));
+        ConvertStats::default()
+    };
+
+    // 多场比赛的文件本身转换成功时只报告其中的各场
+    let entries = opts.manifest.unwrap().into_inner().unwrap();
+    let mut results: Vec<_> = entries
+        .into_iter()
+        .filter(|entry| entry.status != "games")
+        .map(|entry| Validation {
+            parseable: !entry.load_failed,
+            converts: entry.error.is_none(),
+            input: entry.input,
+            events: entry.events,
+            error: entry.error,
+        })
+        .collect();
+    results.sort_by(|a, b| a.input.cmp(&b.input));
+
+    if args.json {
+        let mut stdout = io::stdout().lock();
+        json::to_writer_pretty(&mut stdout, &json::json!({ "files": results }))?;
+        writeln!(stdout)?;
+    } else {
+        let yes_no = |ok| if ok { "yes" } else { "no" };
+        println!("parse  convert  file");
+        for v in &results {
+            let detail = match (&v.error, v.events) {
+                (Some(error), _) => format!(": {error}"),
+                (None, Some(events)) => format!(" ({events} events)"),
+                (None, None) => String::new(),
+            };
+            println!("{:<5}  {:<7}  {}{detail}",
+                     yes_no(v.parseable),
+                     yes_no(v.converts),
+                     v.input.display());
+        }
+    }
+
+    let parseable = results.iter().filter(|v| v.parseable).count();
+    let failed = results.iter().filter(|v| !v.converts).count();
+    eprintln!("{} files: {parseable} parseable, {} converted, {failed} failed",
+              results.len(),
+              results.len() - failed);
+    ensure!(failed == 0, "{failed} of {} files failed validation", results.len());
+    // 其余的错误来自读取目录或压缩包本身
+    ensure!(stats.errors == 0, "{} inputs could not be read", stats.errors);
+    Ok(())
+}
+
+fn convert(args: ConvertArgs) -> Result<()> {
+    log::set_quiet(args.quiet);
+    log::set_verbose(args.verbose);
+    // 按 https://no-color.org 的约定，NO_COLOR 为空时不生效。
+    // JSON 日志由程序读取，消息中不应带有转义序列
+    let log_json = args.log_format == LogFormat::Json;
+    let no_color = args.no_color
+        || log_json
+        || env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    COLOR.store(!no_color && io::stderr().is_terminal(), Ordering::Relaxed);
+    if log_json {
+        log::set_sink(Box::new(log::JsonSink));
+    }
+    if args.progress && args.log_format == LogFormat::Json {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "--progress cannot be used with --log-format json")
+            .exit();
+    }
+
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+
+    // 使用 --files-from 时只有一个位置参数，即输出目录
+    let (input, output) = match (&args.files_from, args.input, args.output) {
+        // --inline 时没有位置参数，总是输出到 stdout
+        (None, None, None) if args.inline.is_some() => (None, PathBuf::from("-")),
+        // --concat 时没有输出目录
+        (None, Some(input), None) if args.concat.is_some() => (Some(input), PathBuf::from("-")),
+        (Some(_), None, None) if args.concat.is_some() => (None, PathBuf::from("-")),
+        _ if args.concat.is_some() => Cli::command()
+            .error(ErrorKind::ArgumentConflict, "--concat replaces <OUTPUT>")
+            .exit(),
+        (None, Some(input), None) if args.stdout => (Some(input), PathBuf::from("-")),
+        _ if args.stdout => Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, "--stdout requires <INPUT>")
+            .exit(),
+        (None, Some(input), Some(output)) => (Some(input), output),
+        (Some(_), Some(output), None) => (None, output),
+        (None, _, _) => Cli::command()
+            .error(ErrorKind::MissingRequiredArgument, "both <INPUT> and <OUTPUT> are required")
+            .exit(),
+        (Some(_), _, _) => Cli::command()
+            .error(ErrorKind::ArgumentConflict, "--files-from takes only <OUTPUT>, not <INPUT>")
+            .exit(),
+    };
+
+    if let Some(template) = &args.output_template {
+        let known = |name: &str| TEMPLATE_PLACEHOLDERS.contains(&name).then(String::new);
+        if let Err(e) = render_output_template(template, known) {
+            Cli::command().error(ErrorKind::InvalidValue, format!("{e:#}")).exit();
+        }
+    }
+
+    if args.split_kyoku && is_stdout(&output) {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "--split-kyoku cannot write to stdout")
+            .exit();
+    }
+    // 多个 JSON 数组首尾相接不是合法的 JSON
+    if args.concat.is_some() && args.output_format == OutputFormat::Array {
+        let message = "--concat cannot be used with --output-format array";
+        Cli::command().error(ErrorKind::ArgumentConflict, message).exit();
+    }
+
+    let download = DownloadOptions {
+        max_retries: args.max_retries,
+        retry_delay: Duration::from_secs(args.retry_delay),
+        user_agent: args.user_agent,
+        headers: args.headers,
+    };
+
+    // 天凤牌谱 URL/ID 先下载到缓存，再按单个文件转换
+    let source = match (input, args.inline) {
+        (Some(input), _) => Some(LogSource::from_input(&input)),
+        (None, Some(inline)) => Some(LogSource::Inline(inline)),
+        (None, None) => None,
+    };
+    let source = match source {
+        Some(LogSource::Tenhou(log_id)) => {
+            Some(LogSource::File(download::tenhou_log(&log_id, &download)?))
+        }
+        source => source,
+    };
+
+    let checkpoint = args.checkpoint.as_deref().map(Checkpoint::open).transpose()?;
+    let concat = args.concat.as_deref().filter(|_| !args.dry_run);
+    let concat = concat.map(ConcatOutput::create).transpose()?;
+    if let Some(checkpoint) = &checkpoint {
+        log!("{} inputs already converted according to checkpoint {:?}",
+             checkpoint.done.len(),
+             checkpoint.path);
+    }
+
+    if let Some(cache_dir) = args.cache_dir.as_ref().filter(|_| !args.dry_run) {
+        fs::create_dir_all(cache_dir)
+            .with_context(|| format!("failed to create cache directory: {cache_dir:?}"))?;
+    }
+
+    // 有多个输入时各文件的事件会依次写到 stdout，难以区分，--stdout 只接受单个文件
+    if args.stdout && !matches!(source, Some(LogSource::File(_))) {
+        let message = "--stdout takes a single log file, not a directory, .zip archive or URL list";
+        Cli::command().error(ErrorKind::InvalidValue, message).exit();
+    }
+    let opts = ConvertOptions {
+        recursive: args.recursive,
+        jobs,
+        dry_run: args.dry_run,
+        verify: args.verify,
+        check_wall: args.check_wall,
+        best_effort: args.best_effort,
+        strict: args.strict,
+        anonymize: args.anonymize,
+        anonymize_salt: args.anonymize_salt,
+        read_buffer_size: args.read_buffer_size,
+        split_kyoku: args.split_kyoku,
+        fail_fast: args.fail_fast,
+        limit: args.limit,
+        limit_attempts: args.limit_attempts,
+        aborted: AtomicBool::new(false),
+        incremental: args.incremental,
+        force: args.force,
+        stdout_marker: !matches!(source, Some(LogSource::File(_) | LogSource::Inline(_)))
+            && args.concat.is_none()
+            && is_stdout(&output),
+        single_game: args.stdout,
+        keep_extension: args.keep_extension,
+        output_suffix: args.output_suffix,
+        output_template: args.output_template,
+        shard: args.shard,
+        concat,
+        output_format: args.output_format,
+        start_time: args.start_time,
+        game_length: args.game_length,
+        only_incomplete: args.only_incomplete,
+        game_index: args.game_index,
+        room: args.room,
+        since: args.since,
+        until: args.until,
+        include_undated: args.include_undated,
+        claimed_outputs: Mutex::default(),
+        seat_stats: args.seat_stats.as_ref().map(|_| Mutex::default()),
+        player_stats: args.player_stats.as_ref().map(|_| Mutex::default()),
+        yaku_stats: args.yaku_stats.as_ref().map(|_| Mutex::default()),
+        efficiency: args.efficiency_export.as_ref().map(|_| Mutex::default()),
+        manifest: args.manifest.as_ref().map(|_| Mutex::default()),
+        checkpoint,
+        cache_dir: args.cache_dir,
+        progress: args.progress.then(Progress::new),
+        download,
+        offline: false,
+        seen_games: args.dedup.then(Mutex::default),
+        archive_entries: Mutex::default(),
+        bench_samples: None,
+    };
+
+    let result = match (&source, &args.files_from) {
+        // 单个文件直接转换
+        (Some(LogSource::File(input)), _) => create_output_dir(&output, &opts).and_then(|()| {
+            log::set_current_file(Some(input));
+            let result = process_file(input, &output, &opts);
+            opts.record(input, &result);
+            result.and_then(|outcome| {
+                // --stdout 时以退出状态区分是否写出了事件
+                let written = matches!(
+                    outcome,
+                    FileOutcome::Converted(_) | FileOutcome::Games { converted: 1.. }
+                );
+                ensure!(written || !args.stdout,
+                        "{input:?} was filtered out or skipped, nothing was written to stdout");
+                Ok(())
+            })
+        }),
+        (Some(LogSource::UrlList(input)), _) => {
+            process_url_list(input, &output, &opts).and_then(|stats| report_stats(&stats))
+        }
+        (Some(LogSource::Zip(input)), _) => {
+            process_zip(input, &output, &opts).and_then(|stats| report_stats(&stats))
+        }
+        (Some(LogSource::Directory(input)), _) => {
+            process_directory(input, &output, &opts).and_then(|stats| report_stats(&stats))
+        }
+        (Some(LogSource::Tenhou(_)), _) => unreachable!("tenhou logs are downloaded above"),
+        (Some(LogSource::Inline(inline)), _) => process_inline(inline, &output, &opts),
+        (None, Some(list)) => {
+            process_file_list(list, &output, &opts).and_then(|stats| report_stats(&stats))
+        }
+        (None, None) => unreachable!("INPUT, --inline or --files-from is required"),
+    };
+
+    // 即使部分文件转换失败，也写出已转换的结果和统计
+    if let Some(concat) = &opts.concat {
+        concat.finish()?;
+    }
+    if let (Some(path), Some(stats)) = (&args.seat_stats, opts.seat_stats) {
+        write_seat_stats(path, &stats.into_inner().unwrap())?;
+    }
+    if let (Some(path), Some(stats)) = (&args.player_stats, opts.player_stats) {
+        write_player_stats(path, &stats.into_inner().unwrap())?;
+    }
+    if let (Some(path), Some(stats)) = (&args.yaku_stats, opts.yaku_stats) {
+        write_yaku_stats(path, &stats.into_inner().unwrap())?;
+    }
+    if let (Some(path), Some(efficiency)) = (&args.efficiency_export, opts.efficiency) {
+        write_efficiency(path, efficiency.into_inner().unwrap())?;
+    }
+    if let (Some(path), Some(manifest)) = (&args.manifest, opts.manifest) {
+        write_manifest(path, manifest.into_inner().unwrap())?;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 与 `--inline` 一样把 `body` 放入 `archive_entries`，再按单个文件处理
+    fn process_body(input_path: &str, body: &str, opts: &ConvertOptions) -> Result<FileOutcome> {
+        let input_path = Path::new(input_path);
+        let body = body.as_bytes().to_vec();
+        opts.archive_entries.lock().unwrap().insert(input_path.to_path_buf(), body);
+        process_file(input_path, Path::new("-"), opts)
+    }
+
+    #[test]
+    fn stdout_rejects_files_with_several_games() {
+        let body = include_str!("fixtures/two_games.json");
+        let opts = ConvertOptions { single_game: true, dry_run: true, ..Default::default() };
+        let err = process_body("two_games.json", body, &opts).err().expect("two games accepted");
+        assert!(format!("{err:#}").contains("contains 2 games"), "{err:#}");
+
+        let opts = ConvertOptions { game_index: Some(1), ..opts };
+        let outcome = process_body("two_games.json", body, &opts);
+        assert!(matches!(outcome, Ok(FileOutcome::Games { converted: 1 })));
+    }
+
+    #[test]
+    fn failed_game_is_counted_once() {
+        let mut games = split_games(include_str!("fixtures/two_games.json")).unwrap();
+        games[1]["log"][0] = json::json!("corrupted");
+        let input_path = Path::new("two_games.json");
+        let body = format!("{}\n{}", games[0], games[1]).into_bytes();
+        let opts = ConvertOptions {
+            jobs: 1,
+            dry_run: true,
+            manifest: Some(Mutex::default()),
+            ..Default::default()
+        };
+        opts.archive_entries.lock().unwrap().insert(input_path.to_path_buf(), body);
+
+        let stats = run_workers(&opts, None, |on_file| {
+            on_file(input_path, Path::new("-"));
+            0
+        });
+        assert_eq!((stats.processed, stats.errors), (1, 1));
+        assert_eq!(stats.failures.len(), 1);
+        assert_eq!(stats.failures[0].path, Path::new("two_games_game1.json"));
+
+        let entries = opts.manifest.unwrap().into_inner().unwrap();
+        let statuses: Vec<_> = entries.iter().map(|entry| entry.status).collect();
+        assert_eq!(statuses, ["converted", "error", "games"]);
+    }
 }